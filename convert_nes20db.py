@@ -0,0 +1,108 @@
+# Converts nes20db (https://forums.nesdev.org/viewtopic.php?t=19940) to the game database
+# format read by GameDb::parse, for nes_core/src/gamedb.txt or --gamedb.
+#
+#   python3 convert_nes20db.py nes20db.xml [roms dir] > gamedb.txt
+#
+# Given a directory of roms, only the games whose iNES headers disagree with the database are
+# kept, which is all the built-in database needs.
+
+import os
+import sys
+import xml.etree.ElementTree as ET
+import zlib
+
+MIRRORING = {"H": "H", "V": "V", "4": "4"}
+REGIONS = {"0": "ntsc", "1": "pal", "3": "dendy"}
+# https://wiki.nesdev.com/w/index.php/NES_2.0#Default_Expansion_Device
+INPUTS = {
+    "1": "controller",
+    "2": "fourscore",
+    "3": "famicom4p",
+    "8": "zapper",
+    "11": "powerpad",
+    "15": "arkanoid",
+    "16": "arkanoid-famicom",
+}
+
+
+def attr(game, tag, name):
+    element = game.find(tag)
+    return None if element is None else element.get(name)
+
+
+def read_games(path):
+    parser = ET.XMLParser(target=ET.TreeBuilder(insert_comments=True))
+    root = ET.parse(path, parser).getroot()
+    for game in root.iter("game"):
+        comments = [c.text.strip() for c in game if c.tag is ET.Comment]
+        title = os.path.splitext(os.path.basename(comments[0]))[0] if comments else "-"
+        crc = attr(game, "rom", "crc32")
+        if crc is None:
+            continue
+        prg_ram = sum(int(attr(game, tag, "size") or 0) for tag in ("prgram", "prgnvram"))
+        yield crc.upper(), {
+            "mapper": attr(game, "pcb", "mapper") or "-",
+            "submapper": attr(game, "pcb", "submapper") or "-",
+            "mirroring": MIRRORING.get(attr(game, "pcb", "mirroring"), "-"),
+            "prg_ram": str(prg_ram),
+            "battery": "B" if attr(game, "pcb", "battery") == "1" else "N",
+            "region": REGIONS.get(attr(game, "console", "region"), "-"),
+            "input": INPUTS.get(attr(game, "expansion", "type"), "-"),
+            "title": title,
+        }
+
+
+def read_header(path):
+    """The CRC32 of a rom's PRG-ROM and CHR-ROM, and what its header says."""
+    with open(path, "rb") as f:
+        data = f.read()
+    if len(data) < 16 or data[:4] != b"NES\x1a":
+        return None
+    nes2 = data[7] & 0x0C == 0x08
+    size = 16 + data[4] * 16384 + data[5] * 8192
+    crc = "{:08X}".format(zlib.crc32(data[16 + (512 if data[6] & 0x4 else 0):][: size - 16]))
+    return crc, {
+        "mapper": str((data[7] & 0xF0) | (data[6] >> 4)),
+        "submapper": str(data[8] >> 4) if nes2 else "0",
+        "mirroring": "4" if data[6] & 0x8 else "V" if data[6] & 0x1 else "H",
+        "battery": "B" if data[6] & 0x2 else "N",
+    }
+
+
+def main():
+    games = dict(read_games(sys.argv[1]))
+    keep = games
+    if len(sys.argv) > 2:
+        keep = {}
+        for dirpath, _, files in os.walk(sys.argv[2]):
+            for name in files:
+                if not name.lower().endswith(".nes"):
+                    continue
+                header = read_header(os.path.join(dirpath, name))
+                if header is None or header[0] not in games:
+                    continue
+                crc, fields = header
+                game = games[crc]
+                if any(game[k] not in ("-", v) for k, v in fields.items()):
+                    keep[crc] = game
+
+    print("# Converted from nes20db by convert_nes20db.py. See GameDb::parse.")
+    print("#")
+    print("# crc32   mapper  sub  mirror  prg_ram  battery  region  input       title")
+    for crc, game in sorted(keep.items(), key=lambda item: item[1]["title"]):
+        print(
+            "{}  {:<7} {:<4} {:<7} {:<8} {:<8} {:<7} {:<11} {}".format(
+                crc,
+                game["mapper"],
+                game["submapper"],
+                game["mirroring"],
+                game["prg_ram"],
+                game["battery"],
+                game["region"],
+                game["input"],
+                game["title"],
+            )
+        )
+
+
+main()
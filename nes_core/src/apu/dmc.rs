@@ -1,12 +1,17 @@
 use serde::{Deserialize, Serialize};
 
-use crate::{cpu::InterruptKind, nes::State};
+use crate::{cpu::InterruptKind, nes::State, region::Region};
 
 /// In units of APU clock.
-const RATE_TABLE: [u16; 16] = [
+const RATE_TABLE_NTSC: [u16; 16] = [
     214, 190, 170, 160, 143, 127, 113, 107, 95, 80, 71, 64, 53, 42, 36, 27,
 ];
 
+/// In units of APU clock.
+const RATE_TABLE_PAL: [u16; 16] = [
+    199, 177, 158, 149, 138, 118, 105, 99, 88, 74, 66, 59, 49, 39, 33, 25,
+];
+
 #[derive(Serialize, Deserialize)]
 pub struct Dmc {
     freq_counter: u16,
@@ -111,6 +116,12 @@ impl Dmc {
         }
     }
 
+    pub fn reset(&mut self) {
+        // The low bit of the output level is left as-is.
+        self.output &= 1;
+        self.irq_pending = false;
+    }
+
     pub fn clock_frame_quarter(&mut self) {}
 
     pub fn clock_frame_half(&mut self) {}
@@ -119,7 +130,7 @@ impl Dmc {
         self.output
     }
 
-    pub fn poke_register(&mut self, register: u16, data: u8) {
+    pub fn poke_register(&mut self, register: u16, data: u8, region: Region) {
         match register {
             0x4010 => {
                 self.irq_enabled = (data & 0b1000_0000) != 0;
@@ -128,7 +139,11 @@ impl Dmc {
                 }
 
                 self.loop_flag = (data & 0b0100_0000) != 0;
-                self.rate = RATE_TABLE[(data & 0b1111) as usize];
+                let rate_table = match region {
+                    Region::Ntsc | Region::Dendy => &RATE_TABLE_NTSC,
+                    Region::Pal => &RATE_TABLE_PAL,
+                };
+                self.rate = rate_table[(data & 0b1111) as usize];
             }
             0x4011 => {
                 self.output = data & 0b0111_1111;
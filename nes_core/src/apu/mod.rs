@@ -1,4 +1,5 @@
-use super::nes::{State, AUDIO_SAMPLES_PER_FRAME};
+use super::nes::{State, AUDIO_BUFFER_LEN};
+use super::region::Region;
 use serde::{Deserialize, Serialize};
 use serde_big_array::big_array;
 
@@ -7,22 +8,21 @@ mod noise;
 mod pulse;
 mod triangle;
 
-const FRAME_INTERVAL: u64 = 7457;
-const FULL_AUDIO_BUFFER_LEN: usize = AUDIO_SAMPLES_PER_FRAME * 40;
+const FULL_AUDIO_BUFFER_LEN: usize = AUDIO_BUFFER_LEN * 40;
 
 const LENGTH_TABLE: [u8; 32] = [
     10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
     192, 24, 72, 26, 16, 28, 32, 30,
 ];
 
-big_array! { BigArray; AUDIO_SAMPLES_PER_FRAME, FULL_AUDIO_BUFFER_LEN }
+big_array! { BigArray; AUDIO_BUFFER_LEN, FULL_AUDIO_BUFFER_LEN }
 
 #[derive(Serialize, Deserialize)]
 pub struct ApuState {
-    /// Downsampled audio buffer (one frame's worth).
+    /// Downsampled audio buffer (one frame's worth, which depends on the region).
     // #[serde(skip)]
     #[serde(with = "BigArray")]
-    pub audio_buffer: [f32; AUDIO_SAMPLES_PER_FRAME],
+    pub audio_buffer: [f32; AUDIO_BUFFER_LEN],
     /// Non-downsampled audio buffer.
     //#[serde(skip)]
     #[serde(with = "BigArray")]
@@ -51,9 +51,9 @@ pub struct ApuState {
 }
 
 impl ApuState {
-    pub fn new() -> ApuState {
+    pub fn new(region: Region) -> ApuState {
         ApuState {
-            audio_buffer: [0.0f32; AUDIO_SAMPLES_PER_FRAME],
+            audio_buffer: [0.0f32; AUDIO_BUFFER_LEN],
             full_audio_buffer: [0.0f32; FULL_AUDIO_BUFFER_LEN],
             audio_index: 0,
             frame_cycle_counter: 0,
@@ -61,7 +61,7 @@ impl ApuState {
             last_cpu_cycle: 0,
             cpu_cycles: 0,
 
-            sequence_counter: FRAME_INTERVAL,
+            sequence_counter: region.apu_frame_interval(),
             next_seq_phase: 0,
             sequencer_mode: 0,
             irq_enabled: false,
@@ -81,8 +81,9 @@ pub fn complete_frame(s: &mut State) {
 
     // Downsample full buffer into audio_buffer (nearest neighbor).
     let num_samples = s.apu.audio_index as f32;
-    for i in 0..AUDIO_SAMPLES_PER_FRAME {
-        let sample_index = ((i as f32) / (AUDIO_SAMPLES_PER_FRAME as f32)) * num_samples;
+    let samples_per_frame = s.region.audio_samples_per_frame();
+    for i in 0..samples_per_frame {
+        let sample_index = ((i as f32) / (samples_per_frame as f32)) * num_samples;
         let sample = s.apu.full_audio_buffer[sample_index as usize];
         s.apu.audio_buffer[i] = sample;
     }
@@ -135,7 +136,7 @@ pub fn emulate(s: &mut State, cycles: u64) {
 
             s.apu.next_seq_phase =
                 (s.apu.next_seq_phase + 1) % (4 + (s.apu.sequencer_mode as usize));
-            s.apu.sequence_counter = 7457;
+            s.apu.sequence_counter = s.region.apu_frame_interval();
         }
 
         // Triangle gets clocked with the CPU.
@@ -188,6 +189,19 @@ fn handle_frame_half(s: &mut State) {
     s.apu.dmc.clock_frame_half();
 }
 
+/// Handles the reset line: all channels are silenced and the frame counter restarts in its
+/// current mode.
+pub fn reset(s: &mut State) {
+    catch_up(s);
+
+    poke_register(s, 0x4015, 0);
+    let frame_counter = (s.apu.sequencer_mode << 7) | ((!s.apu.irq_enabled as u8) << 6);
+    poke_register(s, 0x4017, frame_counter);
+    s.apu.irq_pending = false;
+    s.apu.triangle.reset();
+    s.apu.dmc.reset();
+}
+
 pub fn peek_register(s: &mut State, register: u16) -> u8 {
     catch_up(s);
     if register == 0x4015 {
@@ -212,8 +226,8 @@ pub fn poke_register(s: &mut State, register: u16, data: u8) {
         0x4000..=0x4003 => s.apu.pulse1.poke_register(register, data),
         0x4004..=0x4007 => s.apu.pulse2.poke_register(register, data),
         0x4008..=0x400B => s.apu.triangle.poke_register(register, data),
-        0x400C..=0x400F => s.apu.noise.poke_register(register, data),
-        0x4010..=0x4013 => s.apu.dmc.poke_register(register, data),
+        0x400C..=0x400F => s.apu.noise.poke_register(register, data, s.region),
+        0x4010..=0x4013 => s.apu.dmc.poke_register(register, data, s.region),
         0x4015 => {
             s.apu.pulse1.set_enable_flag((data & 0b0000_0001) != 0);
             s.apu.pulse2.set_enable_flag((data & 0b0000_0010) != 0);
@@ -225,7 +239,7 @@ pub fn poke_register(s: &mut State, register: u16, data: u8) {
             s.apu.sequencer_mode = (data & 0b1000_0000) >> 7;
             s.apu.irq_enabled = (data & 0b0100_0000) == 0;
             s.apu.next_seq_phase = 0;
-            s.apu.sequence_counter = FRAME_INTERVAL;
+            s.apu.sequence_counter = s.region.apu_frame_interval();
 
             if s.apu.sequence_counter == 1 {
                 handle_frame_quarter(s);
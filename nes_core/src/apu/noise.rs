@@ -1,9 +1,14 @@
+use crate::region::Region;
 use serde::{Deserialize, Serialize};
 
-const PERIOD_TABLE: [u16; 16] = [
+const PERIOD_TABLE_NTSC: [u16; 16] = [
     4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
 ];
 
+const PERIOD_TABLE_PAL: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
 #[derive(Serialize, Deserialize)]
 pub struct Noise {
     enabled: bool,
@@ -97,7 +102,7 @@ impl Noise {
         }
     }
 
-    pub fn poke_register(&mut self, register: u16, data: u8) {
+    pub fn poke_register(&mut self, register: u16, data: u8, region: Region) {
         match register {
             0x400C => {
                 self.volume = data & 0b0000_1111;
@@ -107,7 +112,11 @@ impl Noise {
             }
             0x400D => {}
             0x400E => {
-                self.freq_timer = PERIOD_TABLE[(data & 0b1111) as usize];
+                let period_table = match region {
+                    Region::Ntsc | Region::Dendy => &PERIOD_TABLE_NTSC,
+                    Region::Pal => &PERIOD_TABLE_PAL,
+                };
+                self.freq_timer = period_table[(data & 0b1111) as usize];
                 self.shift_mode = data & 0b1000_0000;
             }
             0x400F => {
@@ -35,6 +35,10 @@ impl Triangle {
         }
     }
 
+    pub fn reset(&mut self) {
+        self.sequence_counter = 0;
+    }
+
     /// Clocked every CPU cycle.
     pub fn clock(&mut self) {
         let ultrasonic = self.freq_timer < 2 && self.freq_counter == 0;
@@ -0,0 +1,80 @@
+use super::input::{DeviceKind, InputDevice};
+use super::ppu::PpuState;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+
+/// Potentiometer readings at either end of the knob's travel.
+const POT_MIN: f32 = 0x54 as f32;
+const POT_MAX: f32 = 0xF4 as f32;
+
+/// The Arkanoid "Vaus" paddle controller: a fire button and a potentiometer whose 8-bit
+/// reading is latched on the strobe and shifted out MSB-first, inverted.
+/// The NES version plugs into a controller port (fire on D3, data on D4); the Famicom version
+/// plugs into the expansion port (fire on $4016 D1, data on $4017 D1).
+/// https://wiki.nesdev.com/w/index.php/Arkanoid_controller
+#[derive(Serialize, Deserialize)]
+pub struct Arkanoid {
+    famicom: bool,
+    position: u8,
+    fire: bool,
+    shift: u8,
+    strobe: bool,
+}
+
+impl Arkanoid {
+    pub fn new(famicom: bool) -> Arkanoid {
+        Arkanoid {
+            famicom,
+            position: POT_MIN as u8,
+            fire: false,
+            shift: 0,
+            strobe: false,
+        }
+    }
+
+    /// Sets the knob from 0.0 (fully left) to 1.0 (fully right).
+    pub fn set_state(&mut self, position: f32, fire: bool) {
+        let position = position.clamp(0.0, 1.0);
+        self.position = (POT_MIN + position * (POT_MAX - POT_MIN)) as u8;
+        self.fire = fire;
+    }
+
+    fn read_bit(&mut self) -> u8 {
+        if self.strobe {
+            self.shift = self.position;
+        }
+        let bit = (!self.shift >> 7) & 0x1;
+        self.shift <<= 1;
+        bit
+    }
+}
+
+impl InputDevice for Arkanoid {
+    fn read(&mut self, addr: u16, _ppu: &PpuState) -> u8 {
+        match (self.famicom, addr) {
+            (false, _) => ((self.fire as u8) << 3) | (self.read_bit() << 4),
+            (true, 0x4016) => (self.fire as u8) << 1,
+            (true, _) => self.read_bit() << 1,
+        }
+    }
+
+    fn write(&mut self, data: u8) {
+        let strobe = data & 0x1 > 0;
+        if strobe || self.strobe {
+            self.shift = self.position;
+        }
+        self.strobe = strobe;
+    }
+
+    fn get_kind(&self) -> DeviceKind {
+        if self.famicom {
+            DeviceKind::ArkanoidFamicom
+        } else {
+            DeviceKind::ArkanoidNes
+        }
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -1,3 +1,7 @@
+use super::gamedb::{GameDb, GameInfo};
+use super::input::DeviceKind;
+use super::region::Region;
+
 #[derive(Clone, Default)]
 pub struct RomHeader {
     prg_rom_size: u8, // in 16KB units
@@ -7,18 +11,83 @@ pub struct RomHeader {
     _flags_ext: Vec<u8>,
 }
 
+impl RomHeader {
+    fn is_nes2(&self) -> bool {
+        self.flags7 & 0x0C == 0x08
+    }
+
+    // https://wiki.nesdev.com/w/index.php/NES_2.0#Submapper_number
+    fn submapper(&self) -> Option<u8> {
+        if self.is_nes2() {
+            Some(self._flags_ext[0] >> 4)
+        } else {
+            None
+        }
+    }
+
+    // https://wiki.nesdev.com/w/index.php/NES_2.0#PRG-.28NV.29RAM.2FEEPROM
+    // https://wiki.nesdev.com/w/index.php/INES#Flags_8
+    fn prg_ram_size(&self) -> usize {
+        if self.is_nes2() {
+            // Volatile and battery-backed RAM, as shift counts.
+            [self._flags_ext[2] & 0xF, self._flags_ext[2] >> 4]
+                .iter()
+                .map(|&shift| if shift == 0 { 0 } else { 64 << shift })
+                .sum()
+        } else {
+            // 0 means 8KB, for compatibility.
+            8192 * (self._flags_ext[0] as usize).max(1)
+        }
+    }
+
+    // https://wiki.nesdev.com/w/index.php/NES_2.0#CPU.2FPPU_Timing
+    fn region(&self) -> Option<Region> {
+        if self.is_nes2() {
+            match self._flags_ext[4] & 0b11 {
+                0 => Some(Region::Ntsc),
+                1 => Some(Region::Pal),
+                3 => Some(Region::Dendy),
+                // Multi-region: runs anywhere, so leave it up to the user.
+                _ => None,
+            }
+        } else if self._flags_ext[4..8].iter().all(|&b| b == 0) && self._flags_ext[1] & 0x1 != 0 {
+            // iNES flags 9 is rarely set, and only trustworthy when the rest of the header
+            // isn't garbage (like "DiskDude!").
+            Some(Region::Pal)
+        } else {
+            None
+        }
+    }
+}
+
 #[derive(Clone, Default)]
 pub struct Cartridge {
     pub(crate) _header: RomHeader,
     pub(crate) prg_rom: Vec<u8>,
     pub(crate) chr_rom: Vec<u8>,
     pub(crate) mapper_id: u8,
+    /// Which variant of the mapper's board, if the header (NES 2.0) or database says.
+    pub(crate) submapper: Option<u8>,
+    /// Bit 0 is vertical (rather than horizontal) mirroring; bit 1 is four-screen.
     pub(crate) mirror_mode: u8,
+    /// The region the header asks for, if it says.
+    pub(crate) region: Option<Region>,
     pub(crate) _extra_data: Vec<u8>,
+    prg_ram_size: usize,
+    battery: bool,
+    /// The input device the game needs, from the game database.
+    pub(crate) input: Option<DeviceKind>,
+    /// From the game database.
+    title: Option<String>,
 }
 
 impl Cartridge {
+    /// Loads an iNES or NES 2.0 file, correcting its header from the built-in game database.
     pub fn load(data: &[u8]) -> Cartridge {
+        Cartridge::load_with_db(data, &GameDb::embedded())
+    }
+
+    pub fn load_with_db(data: &[u8], db: &GameDb) -> Cartridge {
         assert!(data.len() >= 16);
         assert!(&data[0..4] == [0x4E, 0x45, 0x53, 0x1A], "not an iNES file");
 
@@ -44,13 +113,95 @@ impl Cartridge {
             chr_rom = vec![0; 8192];
         }
 
-        Cartridge {
+        let mut cart = Cartridge {
             prg_rom,
             chr_rom,
             mapper_id: (header.flags7 & 0xF0) | (header.flags6 >> 4),
-            mirror_mode: (header.flags6 & 0x1) | (header.flags6 & 0x8 >> 2),
+            submapper: header.submapper(),
+            mirror_mode: (header.flags6 & 0x1) | ((header.flags6 & 0x8) >> 2),
+            region: header.region(),
+            prg_ram_size: header.prg_ram_size(),
+            battery: header.flags6 & 0x2 != 0,
+            input: None,
+            title: None,
             _header: header,
             _extra_data: extra_data.to_vec(),
+        };
+        // CHR-RAM isn't part of the dump.
+        let chr_rom: &[u8] = if cart._header.chr_rom_size == 0 {
+            &[]
+        } else {
+            &cart.chr_rom
+        };
+        match db.find(&cart.prg_rom, chr_rom).cloned() {
+            Some(info) => cart.apply_game_info(info),
+            None => println!("[cartridge] Not in the game database; trusting the header"),
         }
+        cart
+    }
+
+    /// Overrides the header with what the game database knows, logging what changed.
+    fn apply_game_info(&mut self, info: GameInfo) {
+        println!("[cartridge] Found in database: {}", info.title);
+        fn fix<T: PartialEq + std::fmt::Debug>(name: &str, field: &mut T, value: Option<T>) {
+            match value {
+                Some(value) if value != *field => {
+                    println!("[cartridge] Fixing {}: {:?} -> {:?}", name, field, value);
+                    *field = value;
+                }
+                _ => {}
+            }
+        }
+        if matches!(info.mapper, Some(mapper) if mapper != self.mapper_id) {
+            // The header's submapper was for the wrong mapper.
+            self.submapper = None;
+        }
+        fix("mapper", &mut self.mapper_id, info.mapper);
+        fix("submapper", &mut self.submapper, info.submapper.map(Some));
+        fix("mirroring", &mut self.mirror_mode, info.mirror_mode);
+        fix("PRG-RAM size", &mut self.prg_ram_size, info.prg_ram_size);
+        fix("battery", &mut self.battery, info.battery);
+        fix("region", &mut self.region, info.region.map(Some));
+        self.input = info.input;
+        self.title = Some(info.title);
+    }
+
+    /// The game's title, if it's in the game database.
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// Which variant of the mapper's board, if known. 0 is the default variant.
+    pub fn submapper(&self) -> Option<u8> {
+        self.submapper
+    }
+
+    /// In bytes, including battery-backed RAM.
+    pub fn prg_ram_size(&self) -> usize {
+        self.prg_ram_size
+    }
+
+    /// Whether the cartridge's RAM is battery-backed, so should be saved.
+    pub fn has_battery(&self) -> bool {
+        self.battery
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(flags7: u8, byte8: u8) -> Vec<u8> {
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0x40, flags7, byte8];
+        rom.resize(16 + 16384 + 8192, 0);
+        rom
+    }
+
+    #[test]
+    fn submapper_comes_from_nes2_headers_only() {
+        let nes2 = Cartridge::load(&rom(0x08, 0x30));
+        assert_eq!((nes2.mapper_id, nes2.submapper()), (4, Some(3)));
+        let ines = Cartridge::load(&rom(0x00, 0x30));
+        assert_eq!((ines.mapper_id, ines.submapper()), (4, None));
     }
 }
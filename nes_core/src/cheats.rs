@@ -0,0 +1,180 @@
+/// Game Genie letters, in order of the 4-bit values they encode.
+const LETTERS: &[u8; 16] = b"APZLGITYEOXUKSVN";
+
+/// A decoded Game Genie code. It replaces the value the CPU reads at `addr`, but if `compare`
+/// is set, only when the cartridge's own value matches it (so the patch only hits the
+/// intended PRG bank).
+/// https://wiki.nesdev.com/w/index.php/Game_Genie
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cheat {
+    pub code: String,
+    pub addr: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+    pub enabled: bool,
+}
+
+impl Cheat {
+    /// Decodes a 6- or 8-letter Game Genie code.
+    pub fn decode(code: &str) -> Result<Cheat, String> {
+        let code = code.trim().to_ascii_uppercase();
+        let n = code
+            .bytes()
+            .map(|c| LETTERS.iter().position(|&l| l == c).map(|i| i as u16))
+            .collect::<Option<Vec<u16>>>()
+            .ok_or_else(|| format!("invalid Game Genie code: {}", code))?;
+        if n.len() != 6 && n.len() != 8 {
+            return Err(format!("Game Genie codes have 6 or 8 letters: {}", code));
+        }
+
+        let addr = 0x8000
+            | ((n[3] & 0x7) << 12)
+            | ((n[5] & 0x7) << 8)
+            | ((n[4] & 0x8) << 8)
+            | ((n[2] & 0x7) << 4)
+            | ((n[1] & 0x8) << 4)
+            | (n[4] & 0x7)
+            | (n[3] & 0x8);
+        // The last letter's high bit moves to the compare value in 8-letter codes.
+        let last = n[n.len() - 1];
+        let value = ((n[1] & 0x7) << 4) | ((n[0] & 0x8) << 4) | (n[0] & 0x7) | (last & 0x8);
+        let compare = if n.len() == 8 {
+            Some(((n[7] & 0x7) << 4) | ((n[6] & 0x8) << 4) | (n[6] & 0x7) | (n[5] & 0x8))
+        } else {
+            None
+        };
+
+        Ok(Cheat {
+            code,
+            addr,
+            value: value as u8,
+            compare: compare.map(|c| c as u8),
+            enabled: true,
+        })
+    }
+}
+
+/// The active cheat codes.
+#[derive(Default)]
+pub struct Cheats {
+    list: Vec<Cheat>,
+}
+
+impl Cheats {
+    /// Patches a value read from the cartridge at `addr`.
+    pub fn apply(&self, addr: u16, data: u8) -> u8 {
+        self.list
+            .iter()
+            .filter(|c| c.enabled && c.addr == addr)
+            .find(|c| c.compare.unwrap_or(data) == data)
+            .map_or(data, |c| c.value)
+    }
+
+    /// Adds a code, or re-enables it if it's already there.
+    pub fn add(&mut self, code: &str) -> Result<(), String> {
+        let cheat = Cheat::decode(code)?;
+        match self.list.iter_mut().find(|c| c.code == cheat.code) {
+            Some(existing) => existing.enabled = true,
+            None => self.list.push(cheat),
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, code: &str) {
+        let code = code.trim().to_ascii_uppercase();
+        self.list.retain(|c| c.code != code);
+    }
+
+    /// Returns false if there's no such code.
+    pub fn set_enabled(&mut self, code: &str, enabled: bool) -> bool {
+        let code = code.trim().to_ascii_uppercase();
+        match self.list.iter_mut().find(|c| c.code == code) {
+            Some(cheat) => {
+                cheat.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn list(&self) -> &[Cheat] {
+        &self.list
+    }
+
+    /// One code per line, prefixed with '-' if disabled.
+    pub fn to_text(&self) -> String {
+        self.list
+            .iter()
+            .map(|c| format!("{}{}\n", if c.enabled { "" } else { "-" }, c.code))
+            .collect()
+    }
+
+    /// Adds the codes from `to_text`. Blank lines and lines starting with '#' are skipped.
+    pub fn load_text(&mut self, text: &str) -> Result<(), String> {
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (code, enabled) = match line.strip_prefix('-') {
+                Some(code) => (code, false),
+                None => (line, true),
+            };
+            self.add(code)?;
+            self.set_enabled(code, enabled);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(code: &str) -> (u16, u8, Option<u8>) {
+        let cheat = Cheat::decode(code).unwrap();
+        (cheat.addr, cheat.value, cheat.compare)
+    }
+
+    #[test]
+    fn six_letter_codes() {
+        assert_eq!(decode("SXIOPO"), (0x91D9, 0xAD, None));
+        assert_eq!(decode("GOSSIP"), (0xD1DD, 0x14, None));
+        assert_eq!(decode("AAAAAA"), (0x8000, 0x00, None));
+        assert_eq!(decode(" sxiopo "), (0x91D9, 0xAD, None));
+    }
+
+    #[test]
+    fn eight_letter_codes() {
+        assert_eq!(decode("ZEXPYGLA"), (0x94A7, 0x02, Some(0x03)));
+        assert_eq!(decode("NNNNNNNN"), (0xFFFF, 0xFF, Some(0xFF)));
+    }
+
+    #[test]
+    fn invalid_codes() {
+        assert!(Cheat::decode("SXIOPB").is_err());
+        assert!(Cheat::decode("SXIOP").is_err());
+        assert!(Cheat::decode("SXIOPOA").is_err());
+        assert!(Cheat::decode("").is_err());
+    }
+
+    #[test]
+    fn apply_checks_the_compare_value() {
+        let mut cheats = Cheats::default();
+        cheats.add("ZEXPYGLA").unwrap();
+        assert_eq!(cheats.apply(0x94A7, 0x03), 0x02);
+        assert_eq!(cheats.apply(0x94A7, 0x04), 0x04);
+        assert_eq!(cheats.apply(0x94A8, 0x03), 0x03);
+        cheats.set_enabled("zexpygla", false);
+        assert_eq!(cheats.apply(0x94A7, 0x03), 0x03);
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let mut cheats = Cheats::default();
+        cheats
+            .load_text("# comment\nSXIOPO\n\n-ZEXPYGLA\n")
+            .unwrap();
+        assert_eq!(cheats.to_text(), "SXIOPO\n-ZEXPYGLA\n");
+        assert_eq!(cheats.apply(0x91D9, 0x00), 0xAD);
+    }
+}
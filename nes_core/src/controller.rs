@@ -1,8 +1,11 @@
-use crate::nes::State;
+use crate::input::{DeviceKind, InputDevice};
+use crate::ppu::PpuState;
 
 use serde::{Deserialize, Serialize};
+use std::any::Any;
 
-#[derive(Default, Debug, Serialize, Deserialize)]
+/// The buttons held on a standard controller.
+#[derive(Default, Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct ControllerState {
     pub a: bool,
     pub b: bool,
@@ -12,8 +15,6 @@ pub struct ControllerState {
     pub down: bool,
     pub left: bool,
     pub right: bool,
-
-    index: usize,
 }
 
 impl ControllerState {
@@ -21,29 +22,69 @@ impl ControllerState {
         ControllerState::default()
     }
 
-    pub fn read(&mut self) -> u8 {
-        // TODO: simulate open bus
-        // https://wiki.nesdev.com/w/index.php/Controller_reading#Unconnected_data_lines_and_open_bus
-
-        let status = match self.index {
-            0 => self.a,
-            1 => self.b,
-            2 => self.select,
-            3 => self.start,
-            4 => self.up,
-            5 => self.down,
-            6 => self.left,
-            7 => self.right,
-            _ => false,
-        };
-        self.index += 1;
-        return status as u8;
+    /// The buttons in the order they're shifted out, A first.
+    pub fn bits(&self) -> u8 {
+        (self.a as u8)
+            | (self.b as u8) << 1
+            | (self.select as u8) << 2
+            | (self.start as u8) << 3
+            | (self.up as u8) << 4
+            | (self.down as u8) << 5
+            | (self.left as u8) << 6
+            | (self.right as u8) << 7
+    }
+}
+
+/// A standard controller: a 4021 shift register that's loaded from the buttons while the
+/// strobe is high, and shifted out one bit per read once it goes low.
+/// https://wiki.nesdev.com/w/index.php/Standard_controller
+#[derive(Default, Serialize, Deserialize)]
+pub struct StandardController {
+    pub buttons: ControllerState,
+    shift: u8,
+    strobe: bool,
+}
+
+impl StandardController {
+    pub fn new() -> StandardController {
+        StandardController::default()
+    }
+
+    /// Shifts out the next button. Only D0 is driven.
+    pub(crate) fn read_bit(&mut self) -> u8 {
+        if self.strobe {
+            // Continuously reloading, so this is always the live state of A.
+            return self.buttons.a as u8;
+        }
+        let bit = self.shift & 0x1;
+        // The serial input is tied high, so reads after the eighth return 1.
+        self.shift = (self.shift >> 1) | 0x80;
+        bit
+    }
+
+    pub(crate) fn set_strobe(&mut self, strobe: bool) {
+        // Loaded while the strobe is high, which leaves the buttons latched as of when it falls.
+        if strobe || self.strobe {
+            self.shift = self.buttons.bits();
+        }
+        self.strobe = strobe;
     }
 }
 
-pub fn write(s: &mut State, data: u8) {
-    let strobe = data & 0x1 > 0;
-    if strobe {
-        s.controller1.index = 0;
+impl InputDevice for StandardController {
+    fn read(&mut self, _addr: u16, _ppu: &PpuState) -> u8 {
+        self.read_bit()
+    }
+
+    fn write(&mut self, data: u8) {
+        self.set_strobe(data & 0x1 > 0);
+    }
+
+    fn get_kind(&self) -> DeviceKind {
+        DeviceKind::StandardController
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
     }
 }
@@ -1,3 +1,4 @@
+use super::hooks::{self, HookEvent};
 use super::nes::State;
 use serde::{Deserialize, Serialize};
 
@@ -33,6 +34,9 @@ pub struct CpuState {
 
     pub pending_interrupt: InterruptKind,
     pub cycles: u64,
+
+    /// Last value on the data bus, which is what unmapped reads return ("open bus").
+    pub data_bus: u8,
 }
 
 impl CpuState {
@@ -51,6 +55,7 @@ impl CpuState {
             status_v: false,
             status_n: false,
             pending_interrupt: InterruptKind::None,
+            data_bus: 0,
         }
     }
 }
@@ -90,7 +95,21 @@ pub fn vector_brk(s: &mut State) -> u16 {
     (s.cpu_peek(0xFFFE) as u16) | ((s.cpu_peek(0xFFFF) as u16) << 8)
 }
 
+// Handles the reset line: like an interrupt, but the stack writes are suppressed.
+// https://wiki.nesdev.com/w/index.php/CPU_power_up_state#After_reset
+pub fn reset(s: &mut State) {
+    s.cpu.sp = s.cpu.sp.wrapping_sub(3);
+    s.cpu.status_i = true;
+    s.cpu.pending_interrupt = InterruptKind::None;
+    s.cpu.pc = vector_reset(s);
+    // Reading the vector took 2 of the 7 cycles.
+    s.cpu.cycles += 5;
+}
+
 fn handle_interrupt(s: &mut State) {
+    if s.cpu.pending_interrupt == InterruptKind::NMI && s.hooks.any_nmi {
+        hooks::run(s, HookEvent::Nmi);
+    }
     s.cpu_peek(s.cpu.pc);
     s.cpu_peek(s.cpu.pc);
     let pc = s.cpu.pc;
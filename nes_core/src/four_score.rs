@@ -0,0 +1,140 @@
+use super::controller::ControllerState;
+use super::input::{DeviceKind, InputDevice, Port};
+use super::ppu::PpuState;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+
+/// One half of the NES Four Score, which plugs into both controller ports. Each port reads
+/// two controllers (1 and 3 on port 1, 2 and 4 on port 2), then a signature byte identifying
+/// the port, then 1s.
+/// https://wiki.nesdev.com/w/index.php/Four_player_adapters
+#[derive(Serialize, Deserialize)]
+pub struct FourScore {
+    /// Controller 1 or 2.
+    first: ControllerState,
+    /// Controller 3 or 4.
+    second: ControllerState,
+    /// Read LSB-first, like the controllers.
+    signature: u8,
+    shift: u32,
+    strobe: bool,
+}
+
+impl FourScore {
+    pub fn new(port: Port) -> FourScore {
+        FourScore {
+            first: ControllerState::default(),
+            second: ControllerState::default(),
+            // $4016 reads 0,0,0,1,0,0,0,0; $4017 reads 0,0,1,0,0,0,0,0.
+            signature: if port == Port::Two { 0x04 } else { 0x08 },
+            shift: 0,
+            strobe: false,
+        }
+    }
+
+    /// Sets the buttons of this port's first (0) or second (1) controller.
+    pub fn set_buttons(&mut self, index: usize, buttons: ControllerState) {
+        if index == 0 {
+            self.first = buttons;
+        } else {
+            self.second = buttons;
+        }
+    }
+
+    fn reload(&mut self) {
+        self.shift = (self.first.bits() as u32)
+            | (self.second.bits() as u32) << 8
+            | (self.signature as u32) << 16
+            | 0xFF00_0000;
+    }
+}
+
+impl InputDevice for FourScore {
+    fn read(&mut self, _addr: u16, _ppu: &PpuState) -> u8 {
+        if self.strobe {
+            self.reload();
+        }
+        let bit = (self.shift & 0x1) as u8;
+        self.shift = (self.shift >> 1) | 0x8000_0000;
+        bit
+    }
+
+    fn write(&mut self, data: u8) {
+        let strobe = data & 0x1 > 0;
+        if strobe || self.strobe {
+            self.reload();
+        }
+        self.strobe = strobe;
+    }
+
+    fn get_kind(&self) -> DeviceKind {
+        DeviceKind::FourScore
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// The Famicom 4-player adapter (Hori style), which plugs into the expansion port and reads
+/// controllers 3 and 4 on D1 of $4016 and $4017, alongside the built-in controllers on D0.
+/// Like the Four Score, each is followed by a signature byte (but the other way around).
+/// https://wiki.nesdev.com/w/index.php/Four_player_adapters
+#[derive(Serialize, Deserialize)]
+pub struct FamicomFourPlayer {
+    buttons: [ControllerState; 2],
+    shift: [u16; 2],
+    strobe: bool,
+}
+
+impl FamicomFourPlayer {
+    /// Read LSB-first: $4016 reads 0,0,0,0,0,1,0,0 and $4017 reads 0,0,0,0,1,0,0,0.
+    const SIGNATURES: [u8; 2] = [0x20, 0x10];
+
+    pub fn new() -> FamicomFourPlayer {
+        FamicomFourPlayer {
+            buttons: [ControllerState::default(); 2],
+            shift: [0; 2],
+            strobe: false,
+        }
+    }
+
+    /// Sets the buttons of controller 3 (0) or 4 (1).
+    pub fn set_buttons(&mut self, index: usize, buttons: ControllerState) {
+        self.buttons[index] = buttons;
+    }
+
+    fn reload(&mut self) {
+        for i in 0..2 {
+            self.shift[i] = (self.buttons[i].bits() as u16) | (Self::SIGNATURES[i] as u16) << 8;
+        }
+    }
+}
+
+impl InputDevice for FamicomFourPlayer {
+    fn read(&mut self, addr: u16, _ppu: &PpuState) -> u8 {
+        if self.strobe {
+            self.reload();
+        }
+        let shift = &mut self.shift[(addr & 0x1) as usize];
+        let bit = (*shift & 0x1) as u8;
+        *shift = (*shift >> 1) | 0x8000;
+        bit << 1
+    }
+
+    fn write(&mut self, data: u8) {
+        let strobe = data & 0x1 > 0;
+        if strobe || self.strobe {
+            self.reload();
+        }
+        self.strobe = strobe;
+    }
+
+    fn get_kind(&self) -> DeviceKind {
+        DeviceKind::FamicomFourPlayer
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
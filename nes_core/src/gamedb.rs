@@ -0,0 +1,169 @@
+use super::hash::{crc32, sha1};
+use super::input::DeviceKind;
+use super::region::Region;
+
+/// The built-in database, in the format `GameDb::parse` reads.
+const EMBEDDED: &str = include_str!("gamedb.txt");
+
+/// How a game's cartridge is really wired, for fixing roms with bad headers.
+/// Fields left as `None` aren't known, and the header is trusted.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GameInfo {
+    pub title: String,
+    pub mapper: Option<u8>,
+    pub submapper: Option<u8>,
+    /// 0 for horizontal, 1 for vertical, 2 for four-screen, as in `Cartridge::mirror_mode`.
+    pub mirror_mode: Option<u8>,
+    /// In bytes.
+    pub prg_ram_size: Option<usize>,
+    pub battery: Option<bool>,
+    pub region: Option<Region>,
+    pub input: Option<DeviceKind>,
+}
+
+/// A game is identified by the CRC32 or SHA-1 of its PRG-ROM followed by its CHR-ROM, which
+/// doesn't depend on the header.
+#[derive(Clone, Debug, PartialEq)]
+enum Key {
+    Crc32(u32),
+    Sha1([u8; 20]),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct GameDb {
+    entries: Vec<(Key, GameInfo)>,
+}
+
+impl GameDb {
+    /// The few games built in. Most roms aren't in it; a full database has to be loaded from a
+    /// file with `parse` and added with `extend`.
+    pub fn embedded() -> GameDb {
+        GameDb::parse(EMBEDDED).unwrap()
+    }
+
+    /// Parses a database: one game per line, as whitespace-separated fields
+    ///
+    /// `<crc32 | sha1:<hash>> <mapper> <submapper> <H|V|4> <prg ram bytes> <B|N> <region> <input>
+    /// <title>`
+    ///
+    /// where any field but the hash and title can be `-` if it isn't known. Blank lines and
+    /// lines starting with '#' are skipped.
+    pub fn parse(text: &str) -> Result<GameDb, String> {
+        let mut db = GameDb::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry = parse_line(line).map_err(|e| format!("line {}: {}", i + 1, e))?;
+            db.entries.push(entry);
+        }
+        Ok(db)
+    }
+
+    /// Adds the entries of `other`, which take precedence over this database's.
+    pub fn extend(&mut self, other: GameDb) {
+        let mut entries = other.entries;
+        entries.append(&mut self.entries);
+        self.entries = entries;
+    }
+
+    /// Looks up a game by its PRG-ROM and CHR-ROM.
+    pub fn find(&self, prg_rom: &[u8], chr_rom: &[u8]) -> Option<&GameInfo> {
+        let data = [prg_rom, chr_rom].concat();
+        let crc = crc32(&data);
+        let mut sha = None;
+        self.entries
+            .iter()
+            .find(|(key, _)| match key {
+                Key::Crc32(k) => *k == crc,
+                Key::Sha1(k) => *k == *sha.get_or_insert_with(|| sha1(&data)),
+            })
+            .map(|(_, info)| info)
+    }
+}
+
+/// `None` for "-".
+fn field<T>(s: &str, parse: impl Fn(&str) -> Option<T>) -> Result<Option<T>, String> {
+    if s == "-" {
+        return Ok(None);
+    }
+    parse(s)
+        .map(Some)
+        .ok_or_else(|| format!("invalid field: {}", s))
+}
+
+fn parse_key(s: &str) -> Option<Key> {
+    match s.strip_prefix("sha1:") {
+        Some(hex) if hex.len() == 40 => {
+            let mut hash = [0; 20];
+            for (i, byte) in hash.iter_mut().enumerate() {
+                *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+            }
+            Some(Key::Sha1(hash))
+        }
+        Some(_) => None,
+        None => u32::from_str_radix(s, 16).ok().map(Key::Crc32),
+    }
+}
+
+fn parse_line(line: &str) -> Result<(Key, GameInfo), String> {
+    // The title is the rest of the line, spaces and all.
+    let mut fields = vec![];
+    let mut rest = line;
+    for _ in 0..8 {
+        rest = rest.trim_start();
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        fields.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    fields.push(rest.trim());
+    if fields.iter().any(|f| f.is_empty()) {
+        return Err("expected 9 fields".to_string());
+    }
+    let key = parse_key(fields[0]).ok_or_else(|| format!("invalid hash: {}", fields[0]))?;
+    let info = GameInfo {
+        mapper: field(fields[1], |s| s.parse().ok())?,
+        submapper: field(fields[2], |s| s.parse().ok())?,
+        mirror_mode: field(fields[3], |s| match s {
+            "H" => Some(0),
+            "V" => Some(1),
+            "4" => Some(2),
+            _ => None,
+        })?,
+        prg_ram_size: field(fields[4], |s| s.parse().ok())?,
+        battery: field(fields[5], |s| match s {
+            "B" => Some(true),
+            "N" => Some(false),
+            _ => None,
+        })?,
+        region: field(fields[6], |s| s.parse().ok())?,
+        input: field(fields[7], |s| s.parse().ok())?,
+        title: fields[8].to_string(),
+    };
+    Ok((key, info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_field() {
+        let db = GameDb::parse("# comment\n\n00000000 4 1 - 8192 B pal - Some Game (E)").unwrap();
+        let (key, info) = &db.entries[0];
+        assert_eq!(*key, Key::Crc32(0));
+        let expected = GameInfo {
+            title: "Some Game (E)".to_string(),
+            mapper: Some(4),
+            submapper: Some(1),
+            mirror_mode: None,
+            prg_ram_size: Some(8192),
+            battery: Some(true),
+            region: Some(Region::Pal),
+            input: None,
+        };
+        assert_eq!(*info, expected);
+        assert!(GameDb::parse("00000000 4 H 0 N ntsc").is_err());
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+
+use super::nes::State;
+
+/// When a hook runs.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum HookEvent {
+    /// At a dot of a scanline, before the PPU emulates it. Dots are 0-340, and scanlines
+    /// count from 0 (the first visible line) to the pre-render line.
+    Dot { scanline: u16, dot: u16 },
+    /// When the CPU starts handling an NMI.
+    Nmi,
+    /// When the PPU sets the vblank flag.
+    VblankStart,
+    /// When the mapper raises its IRQ line.
+    MapperIrq,
+}
+
+/// Identifies a hook, for removing it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct HookId(u64);
+
+type Callback = Box<dyn FnMut(&State)>;
+
+/// Callbacks that frontends and tools register to run at points in the frame. They get a
+/// read-only view of the state.
+#[derive(Default)]
+pub struct Hooks {
+    next_id: u64,
+    // Dot hooks by where they run, so checking a dot is one lookup.
+    dots: HashMap<(u16, u16), Vec<(HookId, Callback)>>,
+    events: Vec<(HookId, HookEvent, Callback)>,
+    // Whether there are any hooks of each kind, checked before doing any work for them.
+    pub(crate) any_dot: bool,
+    pub(crate) any_nmi: bool,
+    pub(crate) any_vblank: bool,
+    pub(crate) any_irq: bool,
+    // The mapper's IRQ line at the last dot, to catch it rising.
+    pub(crate) irq_line: bool,
+}
+
+impl Hooks {
+    pub fn add(&mut self, event: HookEvent, callback: Callback) -> HookId {
+        let id = HookId(self.next_id);
+        self.next_id += 1;
+        match event {
+            HookEvent::Dot { scanline, dot } => {
+                self.dots
+                    .entry((scanline, dot))
+                    .or_default()
+                    .push((id, callback));
+            }
+            _ => self.events.push((id, event, callback)),
+        }
+        self.update_flags();
+        id
+    }
+
+    /// Returns whether the hook was there.
+    pub fn remove(&mut self, id: HookId) -> bool {
+        let count = self.len();
+        self.events.retain(|(hook, _, _)| *hook != id);
+        for hooks in self.dots.values_mut() {
+            hooks.retain(|(hook, _)| *hook != id);
+        }
+        self.dots.retain(|_, hooks| !hooks.is_empty());
+        self.update_flags();
+        self.len() != count
+    }
+
+    fn len(&self) -> usize {
+        self.events.len() + self.dots.values().map(|hooks| hooks.len()).sum::<usize>()
+    }
+
+    fn update_flags(&mut self) {
+        let events = &self.events;
+        let any = |kind: HookEvent| events.iter().any(|(_, event, _)| *event == kind);
+        let (nmi, vblank, irq) = (
+            any(HookEvent::Nmi),
+            any(HookEvent::VblankStart),
+            any(HookEvent::MapperIrq),
+        );
+        self.any_nmi = nmi;
+        self.any_vblank = vblank;
+        self.any_irq = irq;
+        self.any_dot = !self.dots.is_empty();
+    }
+}
+
+/// Runs the hooks for an event. The hooks are taken out of the state while they run, so they
+/// can see the rest of it.
+pub fn run(s: &mut State, event: HookEvent) {
+    if let HookEvent::Dot { scanline, dot } = event {
+        if !s.hooks.dots.contains_key(&(scanline, dot)) {
+            return;
+        }
+    }
+    let mut hooks = std::mem::take(&mut s.hooks);
+    match event {
+        HookEvent::Dot { scanline, dot } => {
+            for (_, callback) in hooks.dots.get_mut(&(scanline, dot)).unwrap().iter_mut() {
+                callback(s);
+            }
+        }
+        _ => {
+            for (_, kind, callback) in hooks.events.iter_mut() {
+                if *kind == event {
+                    callback(s);
+                }
+            }
+        }
+    }
+    s.hooks = hooks;
+}
+
+/// Runs the mapper IRQ hooks if the mapper's IRQ line has just gone up.
+pub fn check_mapper_irq(s: &mut State) {
+    let irq = s.mapper.check_irq();
+    let rising = irq && !s.hooks.irq_line;
+    s.hooks.irq_line = irq;
+    if rising {
+        run(s, HookEvent::MapperIrq);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cartridge, Debug, Nes};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// An MMC3 game that turns on NMIs and rendering, and has the mapper raise one IRQ a
+    /// frame: the NMI handler reloads and enables the IRQ counter, the IRQ handler disables it.
+    fn rom() -> Vec<u8> {
+        let code = [
+            0x78, 0xA2, 0xFF, 0x9A, // sei; ldx #$FF; txs
+            0x2C, 0x02, 0x20, 0x10, 0xFB, // wait for vblank
+            0x2C, 0x02, 0x20, 0x10, 0xFB, // wait for vblank
+            0xA9, 0x14, 0x8D, 0x00, 0xC0, // lda #20; sta $C000 (IRQ latch)
+            0x58, // cli
+            0xA9, 0x88, 0x8D, 0x00, 0x20, // lda #$88; sta $2000 (NMI, sprites at $1000)
+            0xA9, 0x18, 0x8D, 0x01, 0x20, // lda #$18; sta $2001 (rendering)
+            0x4C, 0x1E, 0xE0, // jmp *
+        ];
+        let nmi = [0x8D, 0x01, 0xC0, 0x8D, 0x01, 0xE0, 0x40]; // sta $C001; sta $E001; rti
+        let irq = [0x8D, 0x00, 0xE0, 0x40]; // sta $E000; rti
+        let mut prg = vec![0xEA; 0x8000];
+        prg[0x6000..0x6000 + code.len()].copy_from_slice(&code);
+        prg[0x6100..0x6100 + nmi.len()].copy_from_slice(&nmi);
+        prg[0x6200..0x6200 + irq.len()].copy_from_slice(&irq);
+        prg[0x7FFA..].copy_from_slice(&[0x00, 0xE1, 0x00, 0xE0, 0x00, 0xE2]);
+        let mut data = vec![
+            0x4E, 0x45, 0x53, 0x1A, 2, 1, 0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        data.extend(prg);
+        data.extend(vec![0; 0x2000]);
+        data
+    }
+
+    type Log = Rc<RefCell<Vec<(u16, u16)>>>;
+
+    /// Adds a hook that logs the scanline and dot it ran at.
+    fn log_hook(nes: &mut Nes, event: HookEvent) -> (HookId, Log) {
+        let log = Log::default();
+        let hook_log = log.clone();
+        let id = nes.add_hook(event, move |s| {
+            hook_log.borrow_mut().push((s.ppu.scanline, s.ppu.tick))
+        });
+        (id, log)
+    }
+
+    /// Runs a test on a thread with room for the emulator's state, which debug builds copy
+    /// around on the stack.
+    fn run(test: fn()) {
+        std::thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(test)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    fn new_nes() -> Nes {
+        let mut nes = Nes::new(Debug::default(), Cartridge::load(&rom()));
+        for _ in 0..4 {
+            nes.emulate_frame();
+        }
+        nes
+    }
+
+    #[test]
+    fn hooks_run_once_a_frame_at_their_event() {
+        run(|| {
+            let mut nes = new_nes();
+            let (_, dot) = log_hook(
+                &mut nes,
+                HookEvent::Dot {
+                    scanline: 100,
+                    dot: 50,
+                },
+            );
+            let (_, vblank) = log_hook(&mut nes, HookEvent::VblankStart);
+            let (_, nmi) = log_hook(&mut nes, HookEvent::Nmi);
+            let (_, irq) = log_hook(&mut nes, HookEvent::MapperIrq);
+            for frame in 1..=3 {
+                nes.emulate_frame();
+                assert_eq!(dot.borrow().len(), frame);
+                assert_eq!(vblank.borrow().len(), frame);
+                assert_eq!(nmi.borrow().len(), frame);
+                assert_eq!(irq.borrow().len(), frame);
+            }
+            assert!(dot.borrow().iter().all(|&at| at == (100, 50)));
+            assert!(vblank.borrow().iter().all(|&at| at == (241, 1)));
+            assert!(nmi.borrow().iter().all(|&(scanline, _)| scanline == 241));
+            // Reloaded on the pre-render line, the counter reaches 0 on scanline 19, when the
+            // sprite fetches raise A12.
+            assert!(irq.borrow().iter().all(|&at| at == (19, 261)));
+        });
+    }
+
+    #[test]
+    fn removed_hooks_stop_running() {
+        run(|| {
+            let mut nes = new_nes();
+            let (id, log) = log_hook(&mut nes, HookEvent::VblankStart);
+            let (_, kept) = log_hook(&mut nes, HookEvent::VblankStart);
+            nes.emulate_frame();
+            assert!(nes.remove_hook(id));
+            assert!(!nes.remove_hook(id));
+            nes.emulate_frame();
+            assert_eq!(log.borrow().len(), 1);
+            assert_eq!(kept.borrow().len(), 2);
+        });
+    }
+
+    #[test]
+    fn hooks_survive_power_cycle_and_load_state() {
+        run(|| {
+            let mut nes = new_nes();
+            let (_, log) = log_hook(&mut nes, HookEvent::VblankStart);
+            let state = nes.get_state();
+            nes.power_cycle();
+            nes.emulate_frame();
+            assert_eq!(log.borrow().len(), 1);
+            nes.set_state(&state).unwrap();
+            nes.emulate_frame();
+            assert_eq!(log.borrow().len(), 2);
+        });
+    }
+}
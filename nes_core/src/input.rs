@@ -0,0 +1,186 @@
+use super::arkanoid::Arkanoid;
+use super::controller::StandardController;
+use super::four_score::{FamicomFourPlayer, FourScore};
+use super::nes::State;
+use super::power_pad::PowerPad;
+use super::ppu::{self, PpuState};
+use super::zapper::Zapper;
+use serde::{ser::SerializeTuple, Deserialize, Deserializer, Serialize, Serializer};
+use std::any::Any;
+use std::ops::Deref;
+use std::str::FromStr;
+
+erased_serde::serialize_trait_object!(InputDevice);
+
+/// Something plugged into one of the console's input ports.
+/// https://wiki.nesdev.com/w/index.php/Input_devices
+pub trait InputDevice: erased_serde::Serialize {
+    /// Reads $4016 or $4017. Returns the data lines (D0-D4) this device drives; the rest are
+    /// left to open bus.
+    fn read(&mut self, addr: u16, ppu: &PpuState) -> u8;
+
+    /// Handles a write to $4016, whose low bits (OUT0-OUT2) go to every port. OUT0 is the
+    /// controller strobe.
+    fn write(&mut self, data: u8);
+
+    fn get_kind(&self) -> DeviceKind;
+
+    /// For frontends to feed input to a specific device.
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+/// The console's input ports.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Port {
+    /// Controller port 1, read through $4016.
+    One,
+    /// Controller port 2, read through $4017.
+    Two,
+    /// The Famicom expansion port, read through both $4016 and $4017.
+    Expansion,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DeviceKind {
+    None,
+    StandardController,
+    Zapper,
+    /// NES Four Score. Occupies both controller ports.
+    FourScore,
+    /// Famicom 4-player adapter, for the expansion port.
+    FamicomFourPlayer,
+    /// Arkanoid paddle, NES version, for a controller port.
+    ArkanoidNes,
+    /// Arkanoid paddle, Famicom version, for the expansion port.
+    ArkanoidFamicom,
+    PowerPad,
+}
+
+impl FromStr for DeviceKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(DeviceKind::None),
+            "controller" => Ok(DeviceKind::StandardController),
+            "zapper" => Ok(DeviceKind::Zapper),
+            "fourscore" => Ok(DeviceKind::FourScore),
+            "famicom4p" => Ok(DeviceKind::FamicomFourPlayer),
+            "arkanoid" => Ok(DeviceKind::ArkanoidNes),
+            "arkanoid-famicom" => Ok(DeviceKind::ArkanoidFamicom),
+            "powerpad" => Ok(DeviceKind::PowerPad),
+            _ => Err(format!("unknown input device: {}", s)),
+        }
+    }
+}
+
+/// Where a device is usually plugged in.
+pub fn default_port(kind: DeviceKind) -> Port {
+    match kind {
+        DeviceKind::None | DeviceKind::StandardController | DeviceKind::FourScore => Port::One,
+        DeviceKind::Zapper | DeviceKind::ArkanoidNes | DeviceKind::PowerPad => Port::Two,
+        DeviceKind::FamicomFourPlayer | DeviceKind::ArkanoidFamicom => Port::Expansion,
+    }
+}
+
+pub fn make_device(kind: DeviceKind, port: Port) -> Box<dyn InputDevice> {
+    match kind {
+        DeviceKind::None => Box::new(NoDevice),
+        DeviceKind::StandardController => Box::new(StandardController::new()),
+        DeviceKind::Zapper => Box::new(Zapper::new()),
+        DeviceKind::FourScore => Box::new(FourScore::new(port)),
+        DeviceKind::FamicomFourPlayer => Box::new(FamicomFourPlayer::new()),
+        DeviceKind::ArkanoidNes => Box::new(Arkanoid::new(false)),
+        DeviceKind::ArkanoidFamicom => Box::new(Arkanoid::new(true)),
+        DeviceKind::PowerPad => Box::new(PowerPad::new()),
+    }
+}
+
+/// An empty port.
+#[derive(Serialize, Deserialize)]
+pub struct NoDevice;
+
+impl InputDevice for NoDevice {
+    fn read(&mut self, _addr: u16, _ppu: &PpuState) -> u8 {
+        0
+    }
+
+    fn write(&mut self, _data: u8) {}
+
+    fn get_kind(&self) -> DeviceKind {
+        DeviceKind::None
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+pub fn read(s: &mut State, addr: u16) -> u8 {
+    // The Zapper samples what the PPU has drawn so far, so bring it up to this cycle.
+    ppu::catch_up(s);
+    let port = if addr == 0x4016 {
+        &mut s.port1
+    } else {
+        &mut s.port2
+    };
+    (port.read(addr, &s.ppu) | s.expansion.read(addr, &s.ppu)) & 0x1F
+}
+
+pub fn write(s: &mut State, data: u8) {
+    s.port1.write(data);
+    s.port2.write(data);
+    s.expansion.write(data);
+}
+
+/// Takes any pointer to a device, as serde passes the field (a `Box`) by reference.
+pub fn serialize<D, S>(d: &D, serializer: S) -> Result<S::Ok, S::Error>
+where
+    D: Deref<Target = dyn InputDevice>,
+    S: Serializer,
+{
+    let d = d.deref();
+    let mut tuple = serializer.serialize_tuple(2)?;
+    tuple.serialize_element(&d.get_kind())?;
+    tuple.serialize_element(d)?;
+    tuple.end()
+}
+
+struct InputDeviceVisitor;
+
+impl<'de> serde::de::Visitor<'de> for InputDeviceVisitor {
+    type Value = Box<dyn InputDevice>;
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let kind = seq.next_element::<DeviceKind>()?.unwrap();
+        Ok(match kind {
+            DeviceKind::None => Box::new(seq.next_element::<NoDevice>()?.unwrap()),
+            DeviceKind::StandardController => {
+                Box::new(seq.next_element::<StandardController>()?.unwrap())
+            }
+            DeviceKind::Zapper => Box::new(seq.next_element::<Zapper>()?.unwrap()),
+            DeviceKind::FourScore => Box::new(seq.next_element::<FourScore>()?.unwrap()),
+            DeviceKind::FamicomFourPlayer => {
+                Box::new(seq.next_element::<FamicomFourPlayer>()?.unwrap())
+            }
+            DeviceKind::ArkanoidNes | DeviceKind::ArkanoidFamicom => {
+                Box::new(seq.next_element::<Arkanoid>()?.unwrap())
+            }
+            DeviceKind::PowerPad => Box::new(seq.next_element::<PowerPad>()?.unwrap()),
+        })
+    }
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("an input device")
+    }
+}
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Box<dyn InputDevice>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_tuple(2, InputDeviceVisitor)
+}
@@ -1,17 +1,43 @@
 mod apu;
+mod arkanoid;
 mod cartridge;
+mod cheats;
 mod controller;
 mod cpu;
 mod debug;
+mod four_score;
+mod gamedb;
+mod hash;
+mod hooks;
+mod input;
 mod mapper;
 mod nes;
+mod ntsc;
+mod palette;
+mod patch;
+mod power;
+mod power_pad;
 mod ppu;
+mod ram_search;
+mod region;
+mod zapper;
 
 mod mapper_mmc1;
 mod mapper_mmc3;
 mod mapper_nrom;
 
 pub use cartridge::Cartridge;
+pub use cheats::Cheat;
 pub use controller::ControllerState;
 pub use debug::Debug;
-pub use nes::{Nes, AUDIO_SAMPLE_RATE};
+pub use gamedb::{GameDb, GameInfo};
+pub use hash::{crc32, sha1};
+pub use hooks::{HookEvent, HookId};
+pub use input::{DeviceKind, Port};
+pub use nes::{Nes, State, AUDIO_SAMPLE_RATE, FRAME_HEIGHT, FRAME_WIDTH};
+pub use ntsc::{NtscFilter, NtscPreset, NTSC_PRESETS, NTSC_WIDTH};
+pub use palette::{NtscPalette, Palette, BUILTIN_PALETTES};
+pub use patch::apply_patch;
+pub use power::PowerOnPattern;
+pub use ram_search::{parse_number, SearchFilter};
+pub use region::Region;
@@ -1,14 +1,24 @@
 use super::cartridge::Cartridge;
+use super::power::MemoryFiller;
 use serde::{ser::SerializeTuple, Deserialize, Deserializer, Serialize, Serializer};
+use serde_big_array::big_array;
 
 use super::{mapper_mmc1::MapperMmc1, mapper_mmc3::MapperMmc3, mapper_nrom::MapperNrom};
 
 erased_serde::serialize_trait_object!(Mapper);
 
+big_array! { BigArray; 2048 }
+
 pub trait Mapper: erased_serde::Serialize {
-    fn peek(&mut self, addr: u16) -> u8;
+    /// Reads from the cartridge. Returns `None` if nothing on the cartridge drives the data
+    /// bus at this address, leaving it open.
+    fn peek(&mut self, addr: u16) -> Option<u8>;
     fn poke(&mut self, addr: u16, val: u8);
 
+    /// Reads the pattern tables ($0000-$1FFF) without the side effects of a PPU fetch, like
+    /// clocking a scanline counter.
+    fn peek_chr(&self, addr: u16) -> u8;
+
     fn get_id(&self) -> u8;
 
     fn update_cartridge(&mut self, cartridge: Cartridge);
@@ -16,6 +26,34 @@ pub trait Mapper: erased_serde::Serialize {
     fn check_irq(&self) -> bool {
         false
     }
+
+    /// Called when the console's reset button is pressed.
+    fn reset(&mut self) {}
+
+    /// Fills the mapper's RAM (nametables and PRG-RAM) with its power-on contents.
+    fn power_on(&mut self, filler: &mut MemoryFiller);
+
+    /// The PRG-RAM mapped at $6000-$7FFF, for debugging tools and battery saves. Empty if
+    /// there is none.
+    fn prg_ram(&mut self) -> &mut [u8] {
+        &mut []
+    }
+}
+
+/// PRG-RAM for boards that map up to 8KB of it at $6000-$7FFF, sized from the cartridge.
+/// None of the boards here bank PRG-RAM, so any more than 8KB isn't reachable.
+pub fn make_prg_ram(cart: &Cartridge) -> Vec<u8> {
+    vec![0; cart.prg_ram_size().min(8192)]
+}
+
+/// Where `addr` ($6000-$7FFF) lands in PRG-RAM, mirrored if there's less than 8KB, or `None`
+/// if there isn't any.
+pub fn prg_ram_index(ram: &[u8], addr: u16) -> Option<usize> {
+    if ram.is_empty() {
+        None
+    } else {
+        Some((addr & 0x1FFF) as usize % ram.len())
+    }
 }
 
 #[allow(dead_code)]
@@ -28,14 +66,129 @@ pub enum MirrorMode {
     MirrorFour,
 }
 
-pub fn translate_vram(mode: MirrorMode, addr: u16) -> usize {
-    (match mode {
-        MirrorMode::MirrorHorizontal => (addr & 0x3FF) | ((addr & 0x800) >> 1),
-        MirrorMode::MirrorVertical => addr & 0x7FF,
-        MirrorMode::MirrorSingleA => addr & 0x3FF,
-        MirrorMode::MirrorSingleB => 0x400 | (addr & 0x3FF),
-        _ => panic!("Unsupported mirror mode: {:?}", mode),
-    }) as usize
+impl MirrorMode {
+    /// The mirroring wired on the cartridge board, from the header.
+    pub fn from_cartridge(cart: &Cartridge) -> MirrorMode {
+        match cart.mirror_mode {
+            0 => MirrorMode::MirrorHorizontal,
+            1 => MirrorMode::MirrorVertical,
+            _ => MirrorMode::MirrorFour,
+        }
+    }
+}
+
+/// What one of the four 1 KB nametable slots ($2000, $2400, $2800, $2C00) is mapped to.
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum NametableSource {
+    /// One of the console's two 1 KB pages of nametable RAM (CIRAM).
+    Ciram(usize),
+    /// A 1 KB page of nametable RAM on the cartridge.
+    CartRam(usize),
+    /// A 1 KB page of CHR-ROM. Writes are ignored.
+    ChrRom(usize),
+}
+
+/// The nametable address space ($2000-$3EFF), as four 1 KB slots that the mapper can point at
+/// CIRAM, RAM on the cartridge, or CHR-ROM.
+/// https://wiki.nesdev.com/w/index.php/Mirroring#Nametable_Mirroring
+#[derive(Serialize, Deserialize)]
+pub struct Nametables {
+    #[serde(with = "BigArray")]
+    ciram: [u8; 2048],
+    cart_ram: Vec<u8>,
+    slots: [NametableSource; 4],
+    /// Four-screen boards wire each slot to its own 1 KB of cartridge RAM, whatever the
+    /// mapper says.
+    four_screen: bool,
+}
+
+impl Nametables {
+    /// A four-screen `mode` gets 4 KB of cartridge RAM.
+    pub fn new(mode: MirrorMode) -> Nametables {
+        let four_screen = matches!(mode, MirrorMode::MirrorFour);
+        let mut nametables = Nametables {
+            ciram: [0; 2048],
+            cart_ram: vec![0; if four_screen { 4096 } else { 0 }],
+            slots: [NametableSource::Ciram(0); 4],
+            four_screen: false,
+        };
+        nametables.set_mirroring(mode);
+        nametables.four_screen = four_screen;
+        nametables
+    }
+
+    /// Maps the slots for one of the standard arrangements. Does nothing on four-screen boards.
+    pub fn set_mirroring(&mut self, mode: MirrorMode) {
+        if self.four_screen {
+            return;
+        }
+        let pages = match mode {
+            MirrorMode::MirrorHorizontal => [0, 0, 1, 1],
+            MirrorMode::MirrorVertical => [0, 1, 0, 1],
+            MirrorMode::MirrorSingleA => [0, 0, 0, 0],
+            MirrorMode::MirrorSingleB => [1, 1, 1, 1],
+            MirrorMode::MirrorFour => {
+                // The board may not have asked for the RAM up front.
+                self.cart_ram.resize(self.cart_ram.len().max(4096), 0);
+                self.slots = [0, 1, 2, 3].map(NametableSource::CartRam);
+                return;
+            }
+        };
+        self.slots = pages.map(NametableSource::Ciram);
+    }
+
+    /// Points a slot (0-3) somewhere, for mappers with finer control than mirroring. Does
+    /// nothing on four-screen boards.
+    #[allow(dead_code)]
+    pub fn set_slot(&mut self, slot: usize, source: NametableSource) {
+        if self.four_screen {
+            return;
+        }
+        if let NametableSource::CartRam(page) = source {
+            self.set_cart_ram_size((page + 1) * 0x400);
+        }
+        self.slots[slot] = source;
+    }
+
+    /// Adds cartridge nametable RAM, for mappers that have their own. Never shrinks it.
+    #[allow(dead_code)]
+    pub fn set_cart_ram_size(&mut self, size: usize) {
+        self.cart_ram.resize(size.max(self.cart_ram.len()), 0);
+    }
+
+    fn slot(addr: u16) -> (usize, usize) {
+        (((addr >> 10) & 0x3) as usize, (addr & 0x3FF) as usize)
+    }
+
+    /// Reads a nametable byte. `chr_rom` backs any slots mapped to CHR-ROM.
+    pub fn peek(&self, addr: u16, chr_rom: &[u8]) -> u8 {
+        let (slot, offset) = Nametables::slot(addr);
+        match self.slots[slot] {
+            NametableSource::Ciram(page) => self.ciram[(page & 0x1) * 0x400 + offset],
+            NametableSource::CartRam(page) => {
+                self.cart_ram[(page * 0x400 + offset) % self.cart_ram.len()]
+            }
+            NametableSource::ChrRom(page) => chr_rom[(page * 0x400 + offset) % chr_rom.len()],
+        }
+    }
+
+    pub fn poke(&mut self, addr: u16, val: u8) {
+        let (slot, offset) = Nametables::slot(addr);
+        match self.slots[slot] {
+            NametableSource::Ciram(page) => self.ciram[(page & 0x1) * 0x400 + offset] = val,
+            NametableSource::CartRam(page) => {
+                let len = self.cart_ram.len();
+                self.cart_ram[(page * 0x400 + offset) % len] = val;
+            }
+            NametableSource::ChrRom(_) => {}
+        }
+    }
+
+    pub fn power_on(&mut self, filler: &mut MemoryFiller) {
+        filler.fill(&mut self.ciram);
+        filler.fill(&mut self.cart_ram);
+    }
 }
 
 pub fn make_mapper(cart: Cartridge) -> Box<dyn Mapper> {
@@ -86,3 +239,41 @@ where
 {
     deserializer.deserialize_tuple(2, MapperVisitor)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn four_screen_mirroring_without_cart_ram() {
+        let mut nametables = Nametables::new(MirrorMode::MirrorVertical);
+        nametables.set_mirroring(MirrorMode::MirrorFour);
+        for slot in 0..4 {
+            nametables.poke(0x2000 + slot * 0x400, slot as u8 + 1);
+        }
+        for slot in 0..4 {
+            assert_eq!(nametables.peek(0x2000 + slot * 0x400, &[]), slot as u8 + 1);
+        }
+    }
+
+    #[test]
+    fn slots_map_to_each_source() {
+        let chr_rom: Vec<u8> = (0..0x2000).map(|i| (i / 0x400) as u8 + 0x10).collect();
+        let mut nametables = Nametables::new(MirrorMode::MirrorVertical);
+        nametables.set_slot(0, NametableSource::Ciram(1));
+        nametables.set_slot(1, NametableSource::CartRam(1));
+        nametables.set_slot(2, NametableSource::ChrRom(3));
+        nametables.set_slot(3, NametableSource::Ciram(1));
+
+        nametables.poke(0x2005, 0xAA);
+        assert_eq!(nametables.peek(0x2C05, &chr_rom), 0xAA);
+        nametables.poke(0x2405, 0xBB);
+        assert_eq!(nametables.peek(0x2405, &chr_rom), 0xBB);
+        assert_eq!(nametables.peek(0x2005, &chr_rom), 0xAA);
+        nametables.poke(0x2805, 0xCC);
+        assert_eq!(nametables.peek(0x2805, &chr_rom), 0x13);
+
+        nametables.set_slot(0, NametableSource::CartRam(1));
+        assert_eq!(nametables.peek(0x2005, &chr_rom), 0xBB);
+    }
+}
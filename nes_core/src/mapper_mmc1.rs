@@ -1,18 +1,14 @@
 use super::cartridge::Cartridge;
-use super::mapper::{translate_vram, Mapper, MirrorMode};
+use super::mapper::{self, Mapper, MirrorMode, Nametables};
+use super::power::MemoryFiller;
 use serde::{Deserialize, Serialize};
-use serde_big_array::big_array;
-
-big_array! { BigArray; 2048, 8192 }
 
 #[derive(Serialize, Deserialize)]
 pub struct MapperMmc1 {
     #[serde(skip)]
     cart: Cartridge,
-    #[serde(with = "BigArray")]
-    ram: [u8; 8192],
-    #[serde(with = "BigArray")]
-    vram: [u8; 2048],
+    ram: Vec<u8>,
+    nametables: Nametables,
 
     shift_number: u8,
     shift_data: u8,
@@ -21,7 +17,6 @@ pub struct MapperMmc1 {
     reg_chr1: u8,
     reg_prg: u8,
 
-    mirror_mode: MirrorMode,
     offset_prg0: usize,
     offset_prg1: usize,
     offset_chr0: usize,
@@ -33,16 +28,15 @@ impl MapperMmc1 {
 
     pub fn new(cart: Cartridge) -> MapperMmc1 {
         let mut mapper = MapperMmc1 {
+            nametables: Nametables::new(MirrorMode::from_cartridge(&cart)),
+            ram: mapper::make_prg_ram(&cart),
             cart,
-            ram: [0; 8192],
-            vram: [0; 2048],
             shift_number: 0,
             shift_data: 0,
             reg_control: 0x1F, // ???
             reg_chr0: 0,
             reg_chr1: 0,
             reg_prg: 0,
-            mirror_mode: MirrorMode::MirrorHorizontal,
             offset_prg0: 0,
             offset_prg1: 0,
             offset_chr0: 0,
@@ -53,13 +47,14 @@ impl MapperMmc1 {
     }
 
     fn update_mapping(&mut self) {
-        self.mirror_mode = match self.reg_control & 0b11 {
-            0 => MirrorMode::MirrorSingleA,
-            1 => MirrorMode::MirrorSingleB,
-            2 => MirrorMode::MirrorVertical,
-            3 => MirrorMode::MirrorHorizontal,
-            _ => unreachable!(),
-        };
+        self.nametables
+            .set_mirroring(match self.reg_control & 0b11 {
+                0 => MirrorMode::MirrorSingleA,
+                1 => MirrorMode::MirrorSingleB,
+                2 => MirrorMode::MirrorVertical,
+                3 => MirrorMode::MirrorHorizontal,
+                _ => unreachable!(),
+            });
 
         let prg_mode = (self.reg_control & 0b01100) >> 2;
         let chr_mode = (self.reg_control & 0b10000) >> 4;
@@ -112,18 +107,25 @@ impl MapperMmc1 {
 }
 
 impl Mapper for MapperMmc1 {
-    fn peek(&mut self, addr: u16) -> u8 {
-        match addr {
+    fn peek(&mut self, addr: u16) -> Option<u8> {
+        Some(match addr {
             // PPU
             0x0000..=0x0FFF => self.cart.chr_rom[self.offset_chr0 + (addr & 0xFFF) as usize],
             0x1000..=0x1FFF => self.cart.chr_rom[self.offset_chr1 + (addr & 0xFFF) as usize],
-            0x2000..=0x3EFF => self.vram[translate_vram(self.mirror_mode, addr)],
+            0x2000..=0x3EFF => self.nametables.peek(addr, &self.cart.chr_rom),
 
             // CPU 3FFF
-            0x6000..=0x7FFF => self.ram[(addr & 0x1FFF) as usize],
+            0x6000..=0x7FFF => self.ram[mapper::prg_ram_index(&self.ram, addr)?],
             0x8000..=0xBFFF => self.cart.prg_rom[self.offset_prg0 + (addr & 0x3FFF) as usize],
             0xC000..=0xFFFF => self.cart.prg_rom[self.offset_prg1 + (addr & 0x3FFF) as usize],
-            _ => 0,
+            _ => return None,
+        })
+    }
+
+    fn peek_chr(&self, addr: u16) -> u8 {
+        match addr & 0x1FFF {
+            0x0000..=0x0FFF => self.cart.chr_rom[self.offset_chr0 + (addr & 0xFFF) as usize],
+            _ => self.cart.chr_rom[self.offset_chr1 + (addr & 0xFFF) as usize],
         }
     }
 
@@ -132,10 +134,14 @@ impl Mapper for MapperMmc1 {
             // PPU
             0x0000..=0x0FFF => self.cart.chr_rom[self.offset_chr0 + (addr & 0xFFF) as usize] = val,
             0x1000..=0x1FFF => self.cart.chr_rom[self.offset_chr1 + (addr & 0xFFF) as usize] = val,
-            0x2000..=0x3EFF => self.vram[translate_vram(self.mirror_mode, addr)] = val,
+            0x2000..=0x3EFF => self.nametables.poke(addr, val),
 
             // CPU
-            0x6000..=0x7FFF => self.ram[(addr & 0x1FFF) as usize] = val,
+            0x6000..=0x7FFF => {
+                if let Some(i) = mapper::prg_ram_index(&self.ram, addr) {
+                    self.ram[i] = val;
+                }
+            }
             0x8000..=0xFFFF => {
                 // TODO ignore consecutive writes
                 if val & 0x80 > 0 {
@@ -159,6 +165,23 @@ impl Mapper for MapperMmc1 {
         };
     }
 
+    fn reset(&mut self) {
+        // The reset line clears the shift register and forces PRG mode 3, same as a write with bit 7 set.
+        self.shift_data = 0;
+        self.shift_number = 0;
+        self.reg_control |= 0x0C;
+        self.update_mapping();
+    }
+
+    fn power_on(&mut self, filler: &mut MemoryFiller) {
+        self.nametables.power_on(filler);
+        filler.fill(&mut self.ram);
+    }
+
+    fn prg_ram(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
     fn get_id(&self) -> u8 {
         Self::ID
     }
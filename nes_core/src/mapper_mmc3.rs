@@ -1,24 +1,19 @@
 use super::cartridge::Cartridge;
-use super::mapper::{translate_vram, Mapper, MirrorMode};
+use super::mapper::{self, Mapper, MirrorMode, Nametables};
+use super::power::MemoryFiller;
 use serde::{Deserialize, Serialize};
-use serde_big_array::big_array;
-
-big_array! { BigArray; 2048, 8192 }
 
 #[derive(Serialize, Deserialize)]
 pub struct MapperMmc3 {
     #[serde(skip)]
     cart: Cartridge,
-    #[serde(with = "BigArray")]
-    ram: [u8; 8192],
-    #[serde(with = "BigArray")]
-    vram: [u8; 2048],
+    ram: Vec<u8>,
+    nametables: Nametables,
 
     reg_bank_select: u8,
     reg_bank_data: [u8; 8],
     #[allow(dead_code)]
     reg_ram_protect: u8,
-    mirror_mode: MirrorMode,
 
     // 4 x 8 KB banks
     offset_prg: [usize; 4],
@@ -44,11 +39,10 @@ impl MapperMmc3 {
 
     pub fn new(cart: Cartridge) -> MapperMmc3 {
         let mut mapper = MapperMmc3 {
+            nametables: Nametables::new(MirrorMode::from_cartridge(&cart)),
+            ram: mapper::make_prg_ram(&cart),
             cart,
-            ram: [0; 8192],
-            vram: [0; 2048],
 
-            mirror_mode: MirrorMode::MirrorHorizontal,
             reg_bank_select: 0,
             reg_bank_data: [0; 8],
             reg_ram_protect: 0,
@@ -134,11 +128,12 @@ impl MapperMmc3 {
             }
             // Mirroring
             0xA000..=0xBFFF if (addr % 2 == 0) => {
-                self.mirror_mode = if val & 0x1 == 0 {
+                // Ignored by four-screen boards.
+                self.nametables.set_mirroring(if val & 0x1 == 0 {
                     MirrorMode::MirrorVertical
                 } else {
                     MirrorMode::MirrorHorizontal
-                };
+                });
             }
             // PRG RAM Protect
             0xA000..=0xBFFF if (addr % 2 == 1) => self.reg_ram_protect = val,
@@ -181,29 +176,32 @@ impl MapperMmc3 {
 }
 
 impl Mapper for MapperMmc3 {
-    fn peek(&mut self, addr: u16) -> u8 {
-        match addr {
+    fn peek(&mut self, addr: u16) -> Option<u8> {
+        Some(match addr {
             // PPU
             0x0000..=0x1FFF => {
                 self.check_a12(addr);
-
-                let bank = ((addr & 0xFC00) >> 10) as usize;
-                let offset = (addr & 0x3FF) as usize;
-                let location = self.offset_chr[bank] + offset;
-                self.cart.chr_rom[location]
+                self.peek_chr(addr)
             }
-            0x2000..=0x3EFF => self.vram[translate_vram(self.mirror_mode, addr)],
+            0x2000..=0x3EFF => self.nametables.peek(addr, &self.cart.chr_rom),
 
             // CPU
-            0x6000..=0x7FFF => self.ram[(addr & 0x1FFF) as usize],
+            0x6000..=0x7FFF => self.ram[mapper::prg_ram_index(&self.ram, addr)?],
             0x8000..=0xFFFF => {
                 let bank = ((addr & 0x6000) >> 13) as usize;
                 let offset = (addr & 0x1FFF) as usize;
                 let location = self.offset_prg[bank] + offset;
                 self.cart.prg_rom[location]
             }
-            _ => 0,
-        }
+            _ => return None,
+        })
+    }
+
+    fn peek_chr(&self, addr: u16) -> u8 {
+        let bank = ((addr & 0x1C00) >> 10) as usize;
+        let offset = (addr & 0x3FF) as usize;
+        let location = self.offset_chr[bank] + offset;
+        self.cart.chr_rom[location]
     }
 
     fn poke(&mut self, addr: u16, val: u8) {
@@ -217,10 +215,14 @@ impl Mapper for MapperMmc3 {
                 let location = self.offset_chr[bank] + offset;
                 self.cart.chr_rom[location] = val;
             }
-            0x2000..=0x3EFF => self.vram[translate_vram(self.mirror_mode, addr)] = val,
+            0x2000..=0x3EFF => self.nametables.poke(addr, val),
 
             // CPU
-            0x6000..=0x7FFF => self.ram[(addr & 0x1FFF) as usize] = val,
+            0x6000..=0x7FFF => {
+                if let Some(i) = mapper::prg_ram_index(&self.ram, addr) {
+                    self.ram[i] = val;
+                }
+            }
             0x8000..=0xFFFF => self.write_register(addr, val),
             _ => {}
         };
@@ -230,6 +232,15 @@ impl Mapper for MapperMmc3 {
         self.irq_pending
     }
 
+    fn power_on(&mut self, filler: &mut MemoryFiller) {
+        self.nametables.power_on(filler);
+        filler.fill(&mut self.ram);
+    }
+
+    fn prg_ram(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
     fn get_id(&self) -> u8 {
         Self::ID
     }
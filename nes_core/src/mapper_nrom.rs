@@ -1,42 +1,32 @@
 use super::cartridge::Cartridge;
-use super::mapper::{translate_vram, Mapper, MirrorMode};
+use super::mapper::{Mapper, MirrorMode, Nametables};
+use super::power::MemoryFiller;
 use serde::{Deserialize, Serialize};
-use serde_big_array::big_array;
-
-big_array! { BigArray; 2048 }
 
 #[derive(Serialize, Deserialize)]
 pub struct MapperNrom {
     #[serde(skip)]
     cart: Cartridge,
-    #[serde(with = "BigArray")]
-    vram: [u8; 2048],
-    mirror_mode: MirrorMode,
+    nametables: Nametables,
 }
 
 impl MapperNrom {
     pub const ID: u8 = 0;
 
     pub fn new(cart: Cartridge) -> MapperNrom {
-        let mirror_mode = match cart.mirror_mode {
-            0 => MirrorMode::MirrorHorizontal,
-            1 => MirrorMode::MirrorVertical,
-            _ => panic!("Unsupported cart mirror mode: {}", cart.mirror_mode),
-        };
         MapperNrom {
+            nametables: Nametables::new(MirrorMode::from_cartridge(&cart)),
             cart,
-            vram: [0; 2048],
-            mirror_mode,
         }
     }
 }
 
 impl Mapper for MapperNrom {
-    fn peek(&mut self, addr: u16) -> u8 {
-        match addr {
+    fn peek(&mut self, addr: u16) -> Option<u8> {
+        Some(match addr {
             // PPU
             0x0000..=0x1FFF => self.cart.chr_rom[addr as usize],
-            0x2000..=0x3EFF => self.vram[translate_vram(self.mirror_mode, addr)],
+            0x2000..=0x3EFF => self.nametables.peek(addr, &self.cart.chr_rom),
 
             // CPU
             0x8000..=0xFFFF => {
@@ -44,19 +34,27 @@ impl Mapper for MapperNrom {
                 let size = self.cart.prg_rom.len() as u16;
                 self.cart.prg_rom[(offset % size) as usize]
             }
-            _ => 0,
-        }
+            _ => return None,
+        })
     }
 
     fn poke(&mut self, addr: u16, val: u8) {
         match addr {
             // PPU
             0x0000..=0x1FFF => self.cart.chr_rom[addr as usize] = val,
-            0x2000..=0x3EFF => self.vram[translate_vram(self.mirror_mode, addr)] = val,
+            0x2000..=0x3EFF => self.nametables.poke(addr, val),
             _ => {}
         };
     }
 
+    fn peek_chr(&self, addr: u16) -> u8 {
+        self.cart.chr_rom[(addr & 0x1FFF) as usize]
+    }
+
+    fn power_on(&mut self, filler: &mut MemoryFiller) {
+        self.nametables.power_on(filler);
+    }
+
     fn get_id(&self) -> u8 {
         Self::ID
     }
@@ -1,29 +1,44 @@
 use serde::{Deserialize, Serialize};
 use serde_big_array::big_array;
-use std::convert::TryInto;
 
 use super::apu;
+use super::arkanoid::Arkanoid;
 use super::cartridge::Cartridge;
-use super::controller;
+use super::cheats::{Cheat, Cheats};
+use super::controller::{ControllerState, StandardController};
 use super::cpu;
 use super::debug;
+use super::four_score::{FamicomFourPlayer, FourScore};
+use super::hooks::{HookEvent, HookId, Hooks};
+use super::input::{self, DeviceKind, InputDevice, Port};
 use super::mapper;
+use super::palette::Palette;
+use super::power::PowerOnPattern;
+use super::power_pad::PowerPad;
 use super::ppu;
+use super::ram_search::{RamSearch, SearchFilter};
+use super::region::Region;
+use super::zapper::Zapper;
 
 pub const FRAME_DEPTH: usize = 4;
 pub const FRAME_WIDTH: usize = 256;
 pub const FRAME_HEIGHT: usize = 240;
-pub const FRAME_SIZE: usize = FRAME_DEPTH * FRAME_WIDTH * FRAME_HEIGHT;
+pub const FRAME_PIXELS: usize = FRAME_WIDTH * FRAME_HEIGHT;
+pub const FRAME_SIZE: usize = FRAME_DEPTH * FRAME_PIXELS;
 
 /// Samples per second.
 pub const AUDIO_SAMPLE_RATE: usize = 48000;
 
-/// Samples per frame.
-pub const AUDIO_SAMPLES_PER_FRAME: usize = AUDIO_SAMPLE_RATE / 60;
+/// Samples in the longest (50 Hz) frame.
+pub const AUDIO_BUFFER_LEN: usize = AUDIO_SAMPLE_RATE / 50;
 
 pub struct Nes {
     cartridge: Cartridge,
     state: State,
+    power_on: PowerOnPattern,
+    ram_search: RamSearch,
+    /// Whether to convert each frame to RGBA. Off when only the indexed frame is used.
+    rgba_output: bool,
 }
 
 big_array! { BigArray; }
@@ -32,58 +47,349 @@ big_array! { BigArray; }
 pub struct State {
     #[serde(with = "BigArray")]
     pub ram: [u8; 2048],
+    pub region: Region,
     pub cpu: cpu::CpuState,
     pub ppu: ppu::PpuState,
     pub apu: apu::ApuState,
     #[serde(with = "mapper")]
     pub mapper: Box<dyn mapper::Mapper>,
-    pub controller1: controller::ControllerState,
-    pub controller2: controller::ControllerState,
+    #[serde(with = "input")]
+    pub port1: Box<dyn InputDevice>,
+    #[serde(with = "input")]
+    pub port2: Box<dyn InputDevice>,
+    #[serde(with = "input")]
+    pub expansion: Box<dyn InputDevice>,
 
+    #[serde(skip)]
+    pub cheats: Cheats,
+    /// The RGB colors the PPU's palette indices are shown as.
+    #[serde(skip)]
+    pub rgb_palette: Palette,
     #[serde(skip)]
     pub debug: debug::Debug,
+    #[serde(skip)]
+    pub(crate) hooks: Hooks,
 }
 
 impl Nes {
     pub fn new(debug: debug::Debug, cart: Cartridge) -> Nes {
+        Nes::with_power_on(debug, cart, PowerOnPattern::default())
+    }
+
+    pub fn with_power_on(debug: debug::Debug, cart: Cartridge, power_on: PowerOnPattern) -> Nes {
+        let region = cart.region.unwrap_or_default();
+        println!("[nes] Region: {:?}", region);
+        let input = cart.input;
         let mut nes = Nes {
             cartridge: cart.clone(),
-            state: State::new(debug, cart),
+            state: State::new(debug, cart, power_on, region),
+            power_on,
+            ram_search: RamSearch::default(),
+            rgba_output: true,
         };
-        nes.state.cpu.pc = cpu::vector_reset(&mut nes.state);
-        nes.state.cpu.cycles = 7;
-        println!("[nes] Reset to pc = {:#04X}", nes.state.cpu.pc);
-        // nes.state.cpu.pc = 0xC000u16; // nestest auto mode
+        if let Some(kind) = input {
+            let port = input::default_port(kind);
+            println!("[nes] {:?} in {:?}", kind, port);
+            nes.set_input_device(port, kind);
+        }
+        nes.power_up();
         nes
     }
 
+    fn power_up(&mut self) {
+        self.state.cpu.pc = cpu::vector_reset(&mut self.state);
+        self.state.cpu.cycles = 7;
+        println!("[nes] Reset to pc = {:#04X}", self.state.cpu.pc);
+        // self.state.cpu.pc = 0xC000u16; // nestest auto mode
+    }
+
+    /// Presses the reset button. Memory is preserved, and the mapper decides what happens to
+    /// its own registers.
+    pub fn reset(&mut self) {
+        apu::reset(&mut self.state);
+        ppu::reset(&mut self.state);
+        self.state.mapper.reset();
+        cpu::reset(&mut self.state);
+        println!("[nes] Reset to pc = {:#04X}", self.state.cpu.pc);
+    }
+
+    /// Turns the console off and on again, starting over with fresh memory.
+    pub fn power_cycle(&mut self) {
+        let debug = std::mem::take(&mut self.state.debug);
+        let state = State::new(
+            debug,
+            self.cartridge.clone(),
+            self.power_on,
+            self.state.region,
+        );
+        let old_state = std::mem::replace(&mut self.state, state);
+        // Whatever's plugged in stays plugged in.
+        self.state.port1 = old_state.port1;
+        self.state.port2 = old_state.port2;
+        self.state.expansion = old_state.expansion;
+        self.state.cheats = old_state.cheats;
+        self.state.rgb_palette = old_state.rgb_palette;
+        self.state.ppu.unlimited_sprites = old_state.ppu.unlimited_sprites;
+        self.state.hooks = old_state.hooks;
+        // Battery-backed RAM keeps its contents with the power off.
+        if self.cartridge.has_battery() {
+            let mut old_mapper = old_state.mapper;
+            self.state
+                .mapper
+                .prg_ram()
+                .copy_from_slice(old_mapper.prg_ram());
+        }
+        self.power_up();
+    }
+
+    /// The game's title, if it's in the game database.
+    pub fn title(&self) -> Option<&str> {
+        self.cartridge.title()
+    }
+
+    /// Overrides the region picked from the cartridge. The console is power cycled, since
+    /// the APU's timing is set up for the region at power on.
+    pub fn set_region(&mut self, region: Region) {
+        println!("[nes] Region: {:?}", region);
+        self.state.region = region;
+        self.power_cycle();
+    }
+
+    pub fn region(&self) -> Region {
+        self.state.region
+    }
+
+    /// Sets the memory contents used by the next power cycle.
+    pub fn set_power_on_pattern(&mut self, pattern: PowerOnPattern) {
+        self.power_on = pattern;
+    }
+
     pub fn emulate_frame(&mut self) {
         let start_frame = self.state.ppu.frames;
+        self.ram_search.apply_freezes(&mut self.state);
         apu::start_frame(&mut self.state);
         while self.state.ppu.frames == start_frame {
             let _cycles = cpu::emulate(&mut self.state, 1);
             ppu::catch_up(&mut self.state);
         }
         apu::complete_frame(&mut self.state);
+        if self.rgba_output {
+            ppu::update_frame_buffer(&mut self.state);
+        }
         debug::update_overlay(&mut self.state);
     }
 
-    pub fn set_controller1_state(&mut self, state: controller::ControllerState) {
-        self.state.controller1 = state;
+    /// Plugs a device into a port, replacing whatever was there. The Four Score always takes
+    /// both controller ports.
+    pub fn set_input_device(&mut self, port: Port, kind: DeviceKind) {
+        if kind == DeviceKind::FourScore {
+            self.state.port1 = input::make_device(kind, Port::One);
+            self.state.port2 = input::make_device(kind, Port::Two);
+        } else {
+            *self.port_mut(port) = input::make_device(kind, port);
+        }
+    }
+
+    pub fn get_input_device(&self, port: Port) -> DeviceKind {
+        match port {
+            Port::One => self.state.port1.get_kind(),
+            Port::Two => self.state.port2.get_kind(),
+            Port::Expansion => self.state.expansion.get_kind(),
+        }
+    }
+
+    fn port_mut(&mut self, port: Port) -> &mut Box<dyn InputDevice> {
+        match port {
+            Port::One => &mut self.state.port1,
+            Port::Two => &mut self.state.port2,
+            Port::Expansion => &mut self.state.expansion,
+        }
+    }
+
+    /// The device in `port`, if it's a `T`.
+    fn device_mut<T: 'static>(&mut self, port: Port) -> Option<&mut T> {
+        self.port_mut(port).as_any_mut().downcast_mut::<T>()
+    }
+
+    pub fn set_controller1_state(&mut self, state: ControllerState) {
+        self.set_controller_state(0, state);
     }
 
-    pub fn set_controller2_state(&mut self, state: controller::ControllerState) {
-        self.state.controller2 = state;
+    pub fn set_controller2_state(&mut self, state: ControllerState) {
+        self.set_controller_state(1, state);
+    }
+
+    /// Controller 3, through a Four Score or Famicom 4-player adapter.
+    pub fn set_controller3_state(&mut self, state: ControllerState) {
+        self.set_controller_state(2, state);
+    }
+
+    /// Controller 4, through a Four Score or Famicom 4-player adapter.
+    pub fn set_controller4_state(&mut self, state: ControllerState) {
+        self.set_controller_state(3, state);
+    }
+
+    fn set_controller_state(&mut self, player: usize, state: ControllerState) {
+        // Odd players are on port 1, even on port 2.
+        let port = if player & 0x1 == 0 {
+            Port::One
+        } else {
+            Port::Two
+        };
+        if let Some(controller) = self.device_mut::<StandardController>(port) {
+            if player < 2 {
+                controller.buttons = state;
+            }
+        } else if let Some(four_score) = self.device_mut::<FourScore>(port) {
+            four_score.set_buttons(player / 2, state);
+        }
+        if player >= 2 {
+            if let Some(adapter) = self.device_mut::<FamicomFourPlayer>(Port::Expansion) {
+                adapter.set_buttons(player - 2, state);
+            }
+        }
+    }
+
+    /// Aims the Zapper in `port` at a screen pixel (or off-screen) and sets its trigger.
+    pub fn set_zapper_state(&mut self, port: Port, x: i32, y: i32, trigger: bool) {
+        if let Some(zapper) = self.device_mut::<Zapper>(port) {
+            zapper.set_state(x, y, trigger);
+        }
+    }
+
+    /// Turns the Arkanoid paddle in `port` from 0.0 (fully left) to 1.0 (fully right) and
+    /// sets its fire button.
+    pub fn set_arkanoid_state(&mut self, port: Port, position: f32, fire: bool) {
+        if let Some(arkanoid) = self.device_mut::<Arkanoid>(port) {
+            arkanoid.set_state(position, fire);
+        }
+    }
+
+    /// Sets which Power Pad buttons are stepped on. Index 0 is button 1.
+    pub fn set_power_pad_state(&mut self, port: Port, buttons: [bool; 12]) {
+        if let Some(power_pad) = self.device_mut::<PowerPad>(port) {
+            power_pad.set_state(buttons);
+        }
+    }
+
+    /// Adds a Game Genie code, enabled.
+    pub fn add_cheat(&mut self, code: &str) -> Result<(), String> {
+        self.state.cheats.add(code)
+    }
+
+    pub fn remove_cheat(&mut self, code: &str) {
+        self.state.cheats.remove(code);
+    }
+
+    /// Returns false if the code hasn't been added.
+    pub fn set_cheat_enabled(&mut self, code: &str, enabled: bool) -> bool {
+        self.state.cheats.set_enabled(code, enabled)
+    }
+
+    pub fn get_cheats(&self) -> &[Cheat] {
+        self.state.cheats.list()
+    }
+
+    /// The cheat list in a text format for saving to a file.
+    pub fn get_cheats_text(&self) -> String {
+        self.state.cheats.to_text()
+    }
+
+    /// Adds the cheats from text written by `get_cheats_text`.
+    pub fn load_cheats_text(&mut self, text: &str) -> Result<(), String> {
+        self.state.cheats.load_text(text)
+    }
+
+    /// Starts a new RAM search over CPU RAM and PRG-RAM.
+    pub fn ram_search_reset(&mut self) {
+        self.ram_search.reset(&mut self.state);
+    }
+
+    /// Narrows the RAM search to addresses whose value changed as `filter` says since the last
+    /// reset or filter.
+    pub fn ram_search_filter(&mut self, filter: SearchFilter) {
+        self.ram_search.filter(&mut self.state, filter);
+    }
+
+    /// The addresses still in the RAM search, with their values.
+    pub fn ram_search_candidates(&self) -> Vec<(u16, u8)> {
+        self.ram_search.candidates()
+    }
+
+    /// Holds a CPU RAM or PRG-RAM address at `value` every frame. Returns false if the address
+    /// isn't RAM.
+    pub fn freeze_memory(&mut self, addr: u16, value: u8) -> bool {
+        self.ram_search.freeze(&mut self.state, addr, value)
+    }
+
+    pub fn unfreeze_memory(&mut self, addr: u16) {
+        self.ram_search.unfreeze(addr);
+    }
+
+    pub fn get_frozen_memory(&self) -> &[(u16, u8)] {
+        self.ram_search.frozen()
+    }
+
+    /// Changes the colors the picture is drawn with, starting from the next frame.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.state.rgb_palette = palette;
+    }
+
+    /// The cartridge's battery-backed PRG-RAM, to save when the game is closed and restore when
+    /// it's loaded. `None` if the cartridge has no battery.
+    pub fn battery_ram(&mut self) -> Option<&mut [u8]> {
+        if self.cartridge.has_battery() {
+            Some(self.state.mapper.prg_ram())
+        } else {
+            None
+        }
+    }
+
+    /// Registers a callback to run at a point in the frame, which gets a read-only view of the
+    /// state. Emulation isn't slowed down by kinds of events that have no hooks.
+    pub fn add_hook<F>(&mut self, event: HookEvent, callback: F) -> HookId
+    where
+        F: FnMut(&State) + 'static,
+    {
+        self.state.hooks.add(event, Box::new(callback))
+    }
+
+    /// Unregisters a hook. Returns whether it was registered.
+    pub fn remove_hook(&mut self, id: HookId) -> bool {
+        self.state.hooks.remove(id)
+    }
+
+    /// Shows every sprite on a line instead of only the first eight, which removes most
+    /// flicker. Only the picture changes; the game still sees the hardware's limit.
+    pub fn set_unlimited_sprites(&mut self, enabled: bool) {
+        self.state.ppu.unlimited_sprites = enabled;
+    }
+
+    /// Turns the RGBA frame buffer on or off. With it off, `get_frame_buffer` goes stale and
+    /// frames are only available from `get_index_buffer`.
+    pub fn set_rgba_output(&mut self, enabled: bool) {
+        self.rgba_output = enabled;
     }
 
     pub fn get_frame_buffer(&self) -> &[u8; FRAME_SIZE] {
         &self.state.ppu.frame_buffer
     }
 
-    pub fn get_audio_buffer(&self) -> &[f32; AUDIO_SAMPLES_PER_FRAME] {
-        (&self.state.apu.audio_buffer[0..AUDIO_SAMPLES_PER_FRAME])
-            .try_into()
-            .unwrap()
+    /// The last frame as 9-bit pixels: emphasis (red in bit 6, green in bit 7, blue in bit 8)
+    /// and the 6-bit palette index. Convert it with `Palette::to_rgba`.
+    pub fn get_index_buffer(&self) -> &[u16; FRAME_PIXELS] {
+        &self.state.ppu.index_buffer
+    }
+
+    /// Where each line of the last frame starts in the NTSC color subcarrier's cycle, for
+    /// `NtscFilter`.
+    pub fn get_scanline_phases(&self) -> &[u8; FRAME_HEIGHT] {
+        &self.state.ppu.scanline_phase
+    }
+
+    /// The last frame's audio. Its length depends on the region's frame rate.
+    pub fn get_audio_buffer(&self) -> &[f32] {
+        &self.state.apu.audio_buffer[0..self.state.region.audio_samples_per_frame()]
     }
 
     pub fn debug_toggle_overlay(&mut self) {
@@ -105,35 +411,69 @@ impl Nes {
     pub fn set_state(&mut self, data: &[u8]) -> Result<(), ()> {
         let mut new_state: State = bincode::deserialize(data).map_err(|_| ())?;
         new_state.mapper.update_cartridge(self.cartridge.clone());
+        new_state.cheats = std::mem::take(&mut self.state.cheats);
+        new_state.rgb_palette = std::mem::take(&mut self.state.rgb_palette);
+        new_state.ppu.unlimited_sprites = self.state.ppu.unlimited_sprites;
+        new_state.hooks = std::mem::take(&mut self.state.hooks);
         self.state = new_state;
         Ok(())
     }
 }
 
 impl State {
-    pub fn new(debug: debug::Debug, cart: Cartridge) -> State {
+    pub fn new(
+        debug: debug::Debug,
+        cart: Cartridge,
+        power_on: PowerOnPattern,
+        region: Region,
+    ) -> State {
+        let mut filler = power_on.filler();
+        let mut ram = [0; 2048];
+        filler.fill(&mut ram);
+        let mut ppu = ppu::PpuState::new();
+        filler.fill(&mut ppu.oam_1);
+        filler.fill_palette(&mut ppu.palette);
+        let mut mapper = mapper::make_mapper(cart);
+        mapper.power_on(&mut filler);
+
         State {
-            ram: [0; 2048],
+            ram,
+            region,
             cpu: cpu::CpuState::new(),
-            ppu: ppu::PpuState::new(),
-            apu: apu::ApuState::new(),
-            mapper: mapper::make_mapper(cart),
-            controller1: controller::ControllerState::new(),
-            controller2: controller::ControllerState::new(),
+            ppu,
+            apu: apu::ApuState::new(region),
+            mapper,
+            port1: input::make_device(DeviceKind::StandardController, Port::One),
+            port2: input::make_device(DeviceKind::StandardController, Port::Two),
+            expansion: input::make_device(DeviceKind::None, Port::Expansion),
+            cheats: Cheats::default(),
+            rgb_palette: Palette::default(),
             debug,
+            hooks: Hooks::default(),
         }
     }
 
     pub fn cpu_peek(&mut self, addr: u16) -> u8 {
         // https://wiki.nesdev.com/w/index.php/CPU_memory_map
+        // https://wiki.nesdev.com/w/index.php/Open_bus_behavior
+        let open_bus = self.cpu.data_bus;
         let data = match addr {
             0x0000..=0x1FFF => self.ram[(addr & 0x7FF) as usize],
             0x2000..=0x3FFF => ppu::peek_register(self, addr & 0x7),
-            0x4016 => self.controller1.read(),
-            0x4017 => self.controller2.read(),
-            0x4000..=0x401F => apu::peek_register(self, addr),
-            _ /*0x4020..=0xFFFF*/ => self.mapper.peek(addr),
+            // Controllers only drive the low bits.
+            0x4016 | 0x4017 => (open_bus & 0xE0) | input::read(self, addr),
+            0x4015 => (open_bus & 0x20) | apu::peek_register(self, addr),
+            0x4000..=0x401F => open_bus,
+            0x4020..=0x7FFF => self.mapper.peek(addr).unwrap_or(open_bus),
+            _ /*0x8000..=0xFFFF*/ => {
+                let data = self.mapper.peek(addr).unwrap_or(open_bus);
+                self.cheats.apply(addr, data)
+            }
         };
+        // $4015 is internal to the CPU, so its value never reaches the external data bus.
+        if addr != 0x4015 {
+            self.cpu.data_bus = data;
+        }
         self.cpu.cycles += 1;
         // eprintln!("##### read from 0x{:04X}: val: {:02X}. cycle: {}", addr, data, self.cpu.cycles);
         data
@@ -142,11 +482,12 @@ impl State {
     pub fn cpu_poke(&mut self, addr: u16, val: u8) {
         // eprintln!("##### store to 0x{:04X}: val: {}. cycle: {}", addr, val, self.cpu.cycles);
         // https://wiki.nesdev.com/w/index.php/CPU_memory_map
+        self.cpu.data_bus = val;
         match addr {
             0x0000..=0x1FFF => self.ram[(addr & 0x7FF) as usize] = val,
             0x2000..=0x3FFF => ppu::poke_register(self, addr & 0x7, val),
             0x4014 => { /* OAMDMA */ ppu::poke_register(self, addr, val); }
-            0x4016 => { controller::write(self, val) }
+            0x4016 => { input::write(self, val) }
             0x4000..=0x401F => apu::poke_register(self, addr, val),
             _ /* 0x4020..=0xFFFF */ => self.mapper.poke(addr, val),
         }
@@ -164,7 +505,8 @@ impl State {
                 }
                 self.ppu.palette[index]
             }
-            _ => self.mapper.peek(addr),
+            // Nothing drives the PPU data bus, so it holds the low byte of the address.
+            _ => self.mapper.peek(addr).unwrap_or(addr as u8),
         }
     }
 
@@ -183,3 +525,40 @@ impl State {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An NROM game that loops forever, with a NES 2.0 header giving its region.
+    fn rom(region: u8) -> Vec<u8> {
+        let mut data = vec![
+            0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0x08, 0, 0, 0, 0, region, 0, 0, 0,
+        ];
+        let mut prg = vec![0xEA; 0x4000];
+        prg[..3].copy_from_slice(&[0x4C, 0x00, 0x80]); // jmp $8000
+        prg[0x3FFA..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x80]);
+        data.extend(prg);
+        data.extend(vec![0; 0x2000]);
+        data
+    }
+
+    #[test]
+    fn set_region_starts_over_in_the_new_region() {
+        std::thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(|| {
+                let mut nes = Nes::new(debug::Debug::default(), Cartridge::load(&rom(0)));
+                nes.emulate_frame();
+                nes.set_region(Region::Pal);
+                let pal = Nes::new(debug::Debug::default(), Cartridge::load(&rom(1)));
+                assert_eq!(nes.region(), Region::Pal);
+                assert_eq!(nes.get_state(), pal.get_state());
+                nes.power_cycle();
+                assert_eq!(nes.region(), Region::Pal);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+}
@@ -0,0 +1,214 @@
+use super::nes::{FRAME_HEIGHT, FRAME_PIXELS, FRAME_WIDTH};
+use super::palette::{self, NtscPalette, Palette};
+use std::str::FromStr;
+
+/// Width of the filtered picture: two pixels for each of the PPU's.
+pub const NTSC_WIDTH: usize = FRAME_WIDTH * 2;
+
+/// Names accepted by `NtscPreset::from_str`.
+pub const NTSC_PRESETS: [&str; 4] = ["composite", "svideo", "rgb", "monochrome"];
+
+/// Signal samples per PPU pixel. The signal is sampled at the master clock, 12 times per
+/// cycle of the color subcarrier.
+const SAMPLES_PER_PIXEL: usize = 8;
+/// Samples per output pixel.
+const SAMPLES_PER_OUTPUT: usize = SAMPLES_PER_PIXEL * FRAME_WIDTH / NTSC_WIDTH;
+/// The luma filter averages a pixel's worth of samples, less than a color cycle, so some of
+/// the color signal is left over as dot crawl and artifact colors.
+const LUMA_WINDOW: usize = SAMPLES_PER_PIXEL;
+/// The chroma filter averages whole color cycles, so a flat color decodes exactly.
+const CHROMA_WINDOW: usize = 12;
+
+/// How the picture gets from the console to the TV.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NtscPreset {
+    /// Brightness and color share one signal, and each is mistaken for the other.
+    Composite,
+    /// Brightness and color are separate, but color is still blurry.
+    SVideo,
+    /// Every pixel is its own color.
+    Rgb,
+    /// A black and white TV.
+    Monochrome,
+}
+
+impl FromStr for NtscPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "composite" => Ok(NtscPreset::Composite),
+            "svideo" | "s-video" => Ok(NtscPreset::SVideo),
+            "rgb" => Ok(NtscPreset::Rgb),
+            "monochrome" => Ok(NtscPreset::Monochrome),
+            _ => Err(format!(
+                "unknown NTSC preset: {} (presets: {})",
+                s,
+                NTSC_PRESETS.join(", ")
+            )),
+        }
+    }
+}
+
+/// Simulates the NTSC video signal from the PPU and a TV decoding it, for frames from
+/// `Nes::get_index_buffer` and `Nes::get_scanline_phases`.
+/// https://wiki.nesdev.com/w/index.php/NTSC_video
+pub struct NtscFilter {
+    color: NtscPalette,
+    /// How much of the color signal is mistaken for brightness, from 0 to 1.
+    artifacts: f32,
+    /// How much of the brightness edges are mistaken for color, from 0 to 1.
+    fringing: f32,
+    /// How much color smears into the next pixels, from 0 to 1.
+    bleed: f32,
+    /// RGB skips the signal and looks colors up directly.
+    rgb: Option<Palette>,
+    /// The signal of each 9-bit pixel at each phase.
+    waves: Vec<[f32; 12]>,
+    /// The average of each wave: the pixel's brightness without its color.
+    levels: Vec<f32>,
+    carrier: [(f32, f32); 12],
+}
+
+impl NtscFilter {
+    /// A filter for `preset`, decoding colors with `color`'s settings.
+    pub fn new(preset: NtscPreset, color: NtscPalette) -> NtscFilter {
+        let mut color = color;
+        let (artifacts, fringing, bleed) = match preset {
+            NtscPreset::Composite => (0.5, 1.0, 1.0),
+            NtscPreset::SVideo => (0.0, 0.0, 0.5),
+            NtscPreset::Rgb => (0.0, 0.0, 0.0),
+            NtscPreset::Monochrome => {
+                color.saturation = 0.0;
+                (0.5, 0.0, 0.0)
+            }
+        };
+        let rgb = match preset {
+            NtscPreset::Rgb => Some(Palette::generate(&color)),
+            _ => None,
+        };
+        let waves: Vec<[f32; 12]> = (0..512)
+            .map(|pixel| {
+                let mut wave = [0.0; 12];
+                for (phase, v) in wave.iter_mut().enumerate() {
+                    *v = palette::signal(pixel, phase);
+                }
+                wave
+            })
+            .collect();
+        let levels = waves.iter().map(|w| w.iter().sum::<f32>() / 12.0).collect();
+        let mut carrier = [(0.0, 0.0); 12];
+        for (phase, c) in carrier.iter_mut().enumerate() {
+            *c = palette::carrier(phase, color.hue);
+        }
+        NtscFilter {
+            color,
+            artifacts,
+            fringing,
+            bleed,
+            rgb,
+            waves,
+            levels,
+            carrier,
+        }
+    }
+
+    /// Filters a frame of 9-bit pixels into `NTSC_WIDTH` x 240 RGBA.
+    pub fn apply(&self, pixels: &[u16; FRAME_PIXELS], phases: &[u8; FRAME_HEIGHT], out: &mut [u8]) {
+        let len = FRAME_WIDTH * SAMPLES_PER_PIXEL;
+        let mut luma = vec![0.0; len];
+        let mut chroma = vec![0.0; len];
+        let mut levels = vec![0.0; len];
+        for (y, line) in pixels.chunks_exact(FRAME_WIDTH).enumerate() {
+            let out = &mut out[y * NTSC_WIDTH * 4..(y + 1) * NTSC_WIDTH * 4];
+            if let Some(rgb) = &self.rgb {
+                for (x, rgba) in out.chunks_exact_mut(8).enumerate() {
+                    let pixel = line[x] & 0x1FF;
+                    let color = rgb.color((pixel & 0x3F) as u8, (pixel >> 6) as u8);
+                    write_rgba(&mut rgba[0..4], color);
+                    write_rgba(&mut rgba[4..8], color);
+                }
+                continue;
+            }
+
+            // Each line starts a third of a color cycle later than the last, and odd frames
+            // skip a dot, which is what makes the artifacts crawl.
+            let start = phases[y] as usize * SAMPLES_PER_PIXEL;
+            for (x, &pixel) in line.iter().enumerate() {
+                let pixel = pixel as usize & 0x1FF;
+                let level = self.levels[pixel];
+                for k in 0..SAMPLES_PER_PIXEL {
+                    let s = x * SAMPLES_PER_PIXEL + k;
+                    let v = self.waves[pixel][(start + s) % 12];
+                    luma[s] = level + self.artifacts * (v - level);
+                    chroma[s] = v - level;
+                    levels[s] = level;
+                }
+            }
+            if self.fringing > 0.0 {
+                // Sharp changes in brightness have energy at the color frequency.
+                let smooth = Window::new(levels.iter().copied());
+                for (s, c) in chroma.iter_mut().enumerate() {
+                    *c += self.fringing * (levels[s] - smooth.average(s, CHROMA_WINDOW));
+                }
+            }
+
+            let luma = Window::new(luma.iter().copied());
+            let demodulate = |cos: bool| {
+                Window::new(chroma.iter().enumerate().map(|(s, &c)| {
+                    let (i, q) = self.carrier[(start + s) % 12];
+                    c * if cos { i } else { q }
+                }))
+            };
+            let (i_signal, q_signal) = (demodulate(true), demodulate(false));
+            for (x, rgba) in out.chunks_exact_mut(4).enumerate() {
+                let center = x * SAMPLES_PER_OUTPUT + SAMPLES_PER_OUTPUT / 2;
+                let chroma = |signal: &Window| {
+                    let sharp = signal.average(center, CHROMA_WINDOW);
+                    let wide = signal.average(center, CHROMA_WINDOW * 2);
+                    sharp + self.bleed * (wide - sharp)
+                };
+                let color = palette::yiq_to_rgb(
+                    luma.average(center, LUMA_WINDOW),
+                    chroma(&i_signal),
+                    chroma(&q_signal),
+                    &self.color,
+                );
+                write_rgba(rgba, color);
+            }
+        }
+    }
+}
+
+fn write_rgba(out: &mut [u8], color: u32) {
+    out[0] = (color >> 16) as u8;
+    out[1] = (color >> 8) as u8;
+    out[2] = color as u8;
+    out[3] = 255;
+}
+
+/// Running sums of a line of samples, for averaging any stretch of it quickly.
+struct Window(Vec<f32>);
+
+impl Window {
+    fn new(samples: impl Iterator<Item = f32>) -> Window {
+        let mut sums = vec![0.0];
+        let mut total = 0.0;
+        for sample in samples {
+            total += sample;
+            sums.push(total);
+        }
+        Window(sums)
+    }
+
+    /// The average of `width` samples around `center`, cut short at the ends of the line.
+    fn average(&self, center: usize, width: usize) -> f32 {
+        let len = self.0.len() - 1;
+        let start = center.saturating_sub(width / 2).min(len);
+        let end = (start + width).min(len);
+        if end == start {
+            return 0.0;
+        }
+        (self.0[end] - self.0[start]) / (end - start) as f32
+    }
+}
@@ -0,0 +1,300 @@
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+/// The palette this emulator has always used.
+const DEFAULT_COLORS: [u32; 64] = [
+    0x545454, 0x001e74, 0x081090, 0x300088, 0x440064, 0x5c0030, 0x540400, 0x3c1800, 0x202a00,
+    0x083a00, 0x004000, 0x003c00, 0x00323c, 0x000000, 0x000000, 0x000000, 0x989698, 0x084cc4,
+    0x3032ec, 0x5c1ee4, 0x8814b0, 0xa01464, 0x982220, 0x783c00, 0x545a00, 0x287200, 0x087c00,
+    0x007628, 0x006678, 0x000000, 0x000000, 0x000000, 0xeceeec, 0x4c9aec, 0x787cec, 0xb062ec,
+    0xe454ec, 0xec58b4, 0xec6a64, 0xd48820, 0xa0aa00, 0x74c400, 0x4cd020, 0x38cc6c, 0x38b4cc,
+    0x3c3c3c, 0x000000, 0x000000, 0xeceeec, 0xa8ccec, 0xbcbcec, 0xd4b2ec, 0xecaeec, 0xecaed4,
+    0xecb4b0, 0xe4c490, 0xccd278, 0xb4de78, 0xa8e290, 0x98e2b4, 0xa0d6e4, 0xa0a2a0, 0x000000,
+    0x000000,
+];
+
+/// The RGB PPU (2C03) used in Vs. System and PlayChoice-10 machines, as 3-bit levels of red,
+/// green, and blue.
+/// https://wiki.nesdev.com/w/index.php/PPU_palettes#2C03_and_2C05
+const RGB_PPU_COLORS: [u16; 64] = [
+    0o333, 0o014, 0o006, 0o326, 0o403, 0o503, 0o510, 0o420, 0o320, 0o120, 0o031, 0o040, 0o022,
+    0o000, 0o000, 0o000, 0o555, 0o036, 0o027, 0o407, 0o507, 0o704, 0o700, 0o630, 0o430, 0o140,
+    0o040, 0o053, 0o044, 0o000, 0o000, 0o000, 0o777, 0o357, 0o447, 0o637, 0o707, 0o737, 0o740,
+    0o750, 0o660, 0o360, 0o070, 0o276, 0o077, 0o000, 0o000, 0o000, 0o777, 0o567, 0o657, 0o757,
+    0o747, 0o755, 0o764, 0o772, 0o773, 0o572, 0o473, 0o276, 0o467, 0o000, 0o000, 0o000,
+];
+
+/// How much each emphasis bit dims the color channels it doesn't emphasize, for palettes
+/// without their own emphasis colors.
+const EMPHASIS_ATTENUATION: f32 = 0.816328;
+
+// Composite signal levels relative to sync, for each brightness level, low and high.
+// https://wiki.nesdev.com/w/index.php/NTSC_video
+const LOW: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+const HIGH: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+const BLACK: f32 = 0.518;
+const WHITE: f32 = 1.962;
+/// How much an emphasis bit dims the signal during its color's half of the wave.
+const ATTENUATION: f32 = 0.746;
+
+/// Names accepted by `Palette::builtin`.
+pub const BUILTIN_PALETTES: [&str; 3] = ["default", "ntsc", "rgb"];
+
+/// The RGB color (as 0xRRGGBB) of each of the 64 palette indices under each of the 8
+/// combinations of color emphasis.
+#[derive(Clone)]
+pub struct Palette {
+    /// Indexed by emphasis (red in bit 0, green in bit 1, blue in bit 2) * 64 + palette index.
+    colors: Vec<u32>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::from_base(&DEFAULT_COLORS)
+    }
+}
+
+impl Palette {
+    pub fn builtin(name: &str) -> Option<Palette> {
+        match name {
+            "default" => Some(Palette::default()),
+            "ntsc" => Some(Palette::generate(&NtscPalette::default())),
+            "rgb" => {
+                let colors = RGB_PPU_COLORS.map(|c| {
+                    let level = |shift: u16| ((c >> shift) & 0x7) as u32 * 255 / 7;
+                    (level(6) << 16) | (level(3) << 8) | level(0)
+                });
+                Some(Palette::from_base(&colors))
+            }
+            _ => None,
+        }
+    }
+
+    /// Reads a .pal file: 64 or 512 (with emphasis) RGB triples. 512-entry palettes are
+    /// ordered by emphasis as on NTSC: red in bit 0, green in bit 1, blue in bit 2.
+    pub fn from_pal(data: &[u8]) -> Result<Palette, String> {
+        let colors: Vec<u32> = data
+            .chunks_exact(3)
+            .map(|c| ((c[0] as u32) << 16) | ((c[1] as u32) << 8) | c[2] as u32)
+            .collect();
+        match data.len() {
+            192 => {
+                let mut base = [0; 64];
+                base.copy_from_slice(&colors);
+                Ok(Palette::from_base(&base))
+            }
+            1536 => Ok(Palette { colors }),
+            len => Err(format!(
+                "a palette file has 64 or 512 colors (192 or 1536 bytes), not {} bytes",
+                len
+            )),
+        }
+    }
+
+    /// Makes up the emphasized colors by darkening the channels that aren't emphasized.
+    /// https://wiki.nesdev.com/w/index.php/Colour_emphasis
+    fn from_base(base: &[u32; 64]) -> Palette {
+        let mut colors = Vec::with_capacity(512);
+        for emphasis in 0..8 {
+            for &pixel in base.iter() {
+                let mut out = 0;
+                for channel in 0..3 {
+                    let shift = 16 - channel * 8;
+                    let mut value = ((pixel >> shift) & 0xFF) as f32;
+                    for other in 0..3 {
+                        if emphasis & (1 << other) != 0 && other != channel {
+                            value *= EMPHASIS_ATTENUATION;
+                        }
+                    }
+                    out |= (value as u32) << shift;
+                }
+                colors.push(out);
+            }
+        }
+        Palette { colors }
+    }
+
+    /// Decodes the PPU's composite video signal for every color, like a TV would.
+    /// https://wiki.nesdev.com/w/index.php/NTSC_video
+    pub fn generate(params: &NtscPalette) -> Palette {
+        let mut colors = Vec::with_capacity(512);
+        for pixel in 0..512 {
+            // Sample the square wave at the 12 phases of the color subcarrier.
+            let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+            for phase in 0..12 {
+                let v = signal(pixel, phase);
+                let (cos, sin) = carrier(phase, params.hue);
+                y += v;
+                i += v * cos;
+                q += v * sin;
+            }
+            colors.push(yiq_to_rgb(y / 12.0, i / 12.0, q / 12.0, params));
+        }
+        Palette { colors }
+    }
+
+    /// The 0xRRGGBB color of a palette index, with emphasis (red in bit 0, green in bit 1,
+    /// blue in bit 2).
+    pub fn color(&self, index: u8, emphasis: u8) -> u32 {
+        self.colors[((emphasis as usize & 0x7) << 6) | (index as usize & 0x3F)]
+    }
+
+    /// Converts 9-bit pixels (emphasis << 6 | palette index, as from `Nes::get_index_buffer`)
+    /// to RGBA.
+    pub fn to_rgba(&self, pixels: &[u16], out: &mut [u8]) {
+        for (&pixel, rgba) in pixels.iter().zip(out.chunks_exact_mut(4)) {
+            let color = self.colors[pixel as usize & 0x1FF];
+            rgba[0] = (color >> 16) as u8;
+            rgba[1] = (color >> 8) as u8;
+            rgba[2] = color as u8;
+            rgba[3] = 255;
+        }
+    }
+}
+
+/// The low and high levels of a palette index's square wave. Hue 0 is gray at the high level,
+/// hue $D at the low level, and $E-$F are black.
+fn signal_levels(index: usize) -> (f32, f32) {
+    let level = (index >> 4) & 0x3;
+    match index & 0xF {
+        0x0 => (HIGH[level], HIGH[level]),
+        0xD => (LOW[level], LOW[level]),
+        0xE | 0xF => (LOW[1], LOW[1]),
+        _ => (LOW[level], HIGH[level]),
+    }
+}
+
+/// The brightness of a 9-bit pixel, from 0 (black) to 1 (white), regardless of the palette
+/// it's drawn with.
+pub(crate) fn luma(pixel: u16) -> f32 {
+    let index = pixel as usize & 0x3F;
+    let (low, high) = signal_levels(index);
+    let mut signal = (low + high) / 2.0;
+    if index & 0xF < 0xE {
+        // Each emphasis bit dims half of the wave.
+        for bit in 0..3 {
+            if pixel & (1 << (6 + bit)) != 0 {
+                signal *= (1.0 + ATTENUATION) / 2.0;
+            }
+        }
+    }
+    ((signal - BLACK) / (WHITE - BLACK)).clamp(0.0, 1.0)
+}
+
+/// The composite signal of a 9-bit pixel at one of the 12 phases of the color subcarrier,
+/// from 0 at black to 1 at white.
+/// https://wiki.nesdev.com/w/index.php/NTSC_video
+pub(crate) fn signal(pixel: u16, phase: usize) -> f32 {
+    let index = pixel as usize & 0x3F;
+    let emphasis = pixel >> 6;
+    let hue = index & 0xF;
+    let (low, high) = signal_levels(index);
+    let in_phase = |color: usize| (color + phase) % 12 < 6;
+    let mut signal = if in_phase(hue) { high } else { low };
+    // Each emphasis bit darkens the signal during its color's half of the wave.
+    let emphasized = [0xC, 0x4, 0x8]
+        .iter()
+        .enumerate()
+        .any(|(bit, &color)| emphasis & (1 << bit) != 0 && in_phase(color));
+    if emphasized && hue < 0xE {
+        signal *= ATTENUATION;
+    }
+    (signal - BLACK) / (WHITE - BLACK)
+}
+
+/// The reference waves a TV multiplies the signal by to get I and Q, at one of the 12 phases,
+/// with every hue rotated by `hue` degrees.
+pub(crate) fn carrier(phase: usize, hue: f32) -> (f32, f32) {
+    // Lines the phases up with the I and Q axes: the color burst (hue 8) is 180 degrees
+    // from the U axis, which is 123 degrees from I.
+    const HUE_OFFSET: f32 = 93.0;
+    let angle = PI * (phase as f32 + 0.5) / 6.0 + (HUE_OFFSET + hue).to_radians();
+    (angle.cos(), angle.sin())
+}
+
+/// Converts a decoded color to 0xRRGGBB, applying the decoder's picture settings.
+pub(crate) fn yiq_to_rgb(y: f32, i: f32, q: f32, params: &NtscPalette) -> u32 {
+    let (i, q) = (i * params.saturation, q * params.saturation);
+    // FCC YIQ to RGB.
+    let rgb = [
+        y + 0.946882 * i + 0.623557 * q,
+        y - 0.274788 * i - 0.635691 * q,
+        y - 1.108545 * i + 1.709007 * q,
+    ];
+    rgb.iter().fold(0, |acc, &c| {
+        let c = (c * params.contrast + params.brightness).clamp(0.0, 1.0);
+        let c = c.powf(2.2 / params.gamma);
+        (acc << 8) | (c * 255.0).round() as u32
+    })
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    /// Parses a built-in palette name, or `ntsc:` followed by decoder settings (see
+    /// `NtscPalette`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("ntsc:") {
+            Some(params) => Ok(Palette::generate(&params.parse()?)),
+            None => Palette::builtin(s).ok_or_else(|| {
+                format!(
+                    "unknown palette: {} (built-in palettes: {})",
+                    s,
+                    BUILTIN_PALETTES.join(", ")
+                )
+            }),
+        }
+    }
+}
+
+/// Settings for generating a palette from the NTSC signal.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct NtscPalette {
+    /// Rotates every hue, in degrees.
+    pub hue: f32,
+    /// Scales the color signal. 0 is grayscale.
+    pub saturation: f32,
+    pub contrast: f32,
+    /// Added to every channel, from -1 to 1.
+    pub brightness: f32,
+    /// The gamma of the display. 2.2 leaves the decoded colors as they are.
+    pub gamma: f32,
+}
+
+impl Default for NtscPalette {
+    fn default() -> Self {
+        NtscPalette {
+            hue: 0.0,
+            saturation: 1.0,
+            contrast: 1.0,
+            brightness: 0.0,
+            gamma: 2.2,
+        }
+    }
+}
+
+impl FromStr for NtscPalette {
+    type Err = String;
+
+    /// Parses comma-separated settings, like `hue=-15,saturation=1.2`. The rest are defaults.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut params = NtscPalette::default();
+        for setting in s.split(',').filter(|s| !s.is_empty()) {
+            let mut parts = setting.splitn(2, '=');
+            let name = parts.next().unwrap();
+            let value: f32 = parts
+                .next()
+                .and_then(|v| v.parse().ok())
+                .ok_or_else(|| format!("invalid palette setting: {}", setting))?;
+            match name {
+                "hue" => params.hue = value,
+                "saturation" => params.saturation = value,
+                "contrast" => params.contrast = value,
+                "brightness" => params.brightness = value,
+                "gamma" => params.gamma = value,
+                _ => return Err(format!("unknown palette setting: {}", name)),
+            }
+        }
+        Ok(params)
+    }
+}
@@ -0,0 +1,430 @@
+use super::hash::crc32;
+
+/// Applies an IPS, BPS or UPS patch (detected from its header) to a ROM file.
+/// BPS and UPS patches carry checksums of the ROM they expect and the ROM they produce; both
+/// are checked.
+pub fn apply_patch(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.starts_with(b"PATCH") {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(b"BPS1") {
+        apply_bps(rom, patch)
+    } else if patch.starts_with(b"UPS1") {
+        apply_ups(rom, patch)
+    } else {
+        Err("unknown patch format".to_string())
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Reader<'a> {
+        Reader { data, pos }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .pos
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.pos..end))
+            .ok_or_else(|| "patch is truncated".to_string())?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn big_endian(&mut self, len: usize) -> Result<usize, String> {
+        Ok(self
+            .bytes(len)?
+            .iter()
+            .fold(0, |acc, &b| (acc << 8) | b as usize))
+    }
+
+    /// The variable-length integers used by BPS and UPS.
+    fn number(&mut self) -> Result<usize, String> {
+        let overflow = || "patch number overflows".to_string();
+        let mut data = 0usize;
+        let mut shift = 1usize;
+        loop {
+            let x = self.byte()? as usize;
+            data = (x & 0x7F)
+                .checked_mul(shift)
+                .and_then(|n| data.checked_add(n))
+                .ok_or_else(overflow)?;
+            if x & 0x80 != 0 {
+                return Ok(data);
+            }
+            shift = shift.checked_mul(0x80).ok_or_else(overflow)?;
+            data = data.checked_add(shift).ok_or_else(overflow)?;
+        }
+    }
+}
+
+/// https://zerosoft.zophar.net/ips.php
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let mut out = rom.to_vec();
+    let mut reader = Reader::new(patch, 5);
+    loop {
+        if reader.data.get(reader.pos..reader.pos + 3) == Some(b"EOF") {
+            reader.pos += 3;
+            break;
+        }
+        let offset = reader.big_endian(3)?;
+        let size = reader.big_endian(2)?;
+        let (len, data) = if size == 0 {
+            // Run-length encoded.
+            let len = reader.big_endian(2)?;
+            (len, vec![reader.byte()?; len])
+        } else {
+            (size, reader.bytes(size)?.to_vec())
+        };
+        if out.len() < offset + len {
+            out.resize(offset + len, 0);
+        }
+        out[offset..offset + len].copy_from_slice(&data);
+    }
+    // An extension: the file's new size, if it should shrink.
+    if let Ok(truncate) = reader.big_endian(3) {
+        out.truncate(truncate);
+    }
+    Ok(out)
+}
+
+/// Checks the source, target and patch CRC-32s at the end of a BPS or UPS patch.
+fn check_footer(patch: &[u8], source: &[u8], target: &[u8]) -> Result<(), String> {
+    let footer = &patch[patch.len() - 12..];
+    let crc =
+        |i: usize| u32::from_le_bytes([footer[i], footer[i + 1], footer[i + 2], footer[i + 3]]);
+    if crc(8) != crc32(&patch[..patch.len() - 4]) {
+        return Err("patch is corrupt (checksum mismatch)".to_string());
+    }
+    if crc(0) != crc32(source) {
+        return Err(format!(
+            "patch is for a different ROM (expected CRC32 {:08X}, got {:08X})",
+            crc(0),
+            crc32(source)
+        ));
+    }
+    if crc(4) != crc32(target) {
+        return Err("patched ROM doesn't match the patch's checksum".to_string());
+    }
+    Ok(())
+}
+
+/// https://www.romhacking.net/documents/746/
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.len() < 4 + 12 {
+        return Err("patch is truncated".to_string());
+    }
+    let mut reader = Reader::new(&patch[..patch.len() - 12], 4);
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    let metadata_size = reader.number()?;
+    reader.bytes(metadata_size)?;
+    if source_size != rom.len() {
+        return Err(format!(
+            "patch is for a ROM of {} bytes, not {}",
+            source_size,
+            rom.len()
+        ));
+    }
+
+    let mut out = Vec::new();
+    let mut source_offset = 0usize;
+    let mut target_offset = 0usize;
+    let relative = |offset: usize, data: usize| {
+        let delta = data >> 1;
+        if data & 0x1 != 0 {
+            offset.checked_sub(delta)
+        } else {
+            offset.checked_add(delta)
+        }
+        .ok_or_else(|| "patch offset out of range".to_string())
+    };
+    let out_of_range = || "patch offset out of range".to_string();
+    let range = |start: usize, len: usize| start.checked_add(len).map(|end| start..end);
+    while reader.pos < reader.data.len() {
+        let data = reader.number()?;
+        let len = (data >> 2) + 1;
+        match data & 0x3 {
+            // SourceRead
+            0 => {
+                let bytes = range(out.len(), len)
+                    .and_then(|range| rom.get(range))
+                    .ok_or_else(out_of_range)?;
+                out.extend_from_slice(bytes);
+            }
+            // TargetRead
+            1 => out.extend_from_slice(reader.bytes(len)?),
+            // SourceCopy
+            2 => {
+                source_offset = relative(source_offset, reader.number()?)?;
+                let bytes = range(source_offset, len)
+                    .and_then(|range| rom.get(range))
+                    .ok_or_else(out_of_range)?;
+                out.extend_from_slice(bytes);
+                source_offset += len;
+            }
+            // TargetCopy: may overlap what it's writing, so copy a byte at a time.
+            _ => {
+                target_offset = relative(target_offset, reader.number()?)?;
+                for _ in 0..len {
+                    let byte = *out.get(target_offset).ok_or_else(out_of_range)?;
+                    out.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+    if out.len() != target_size {
+        return Err("patch produced the wrong size ROM".to_string());
+    }
+    check_footer(patch, rom, &out)?;
+    Ok(out)
+}
+
+/// http://fileformats.archiveteam.org/wiki/UPS_(binary_patch_format)
+fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.len() < 4 + 12 {
+        return Err("patch is truncated".to_string());
+    }
+    let mut reader = Reader::new(&patch[..patch.len() - 12], 4);
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    if source_size != rom.len() {
+        return Err(format!(
+            "patch is for a ROM of {} bytes, not {}",
+            source_size,
+            rom.len()
+        ));
+    }
+
+    let mut out = rom.to_vec();
+    out.resize(target_size, 0);
+    let mut pos = 0usize;
+    while reader.pos < reader.data.len() {
+        pos = pos
+            .checked_add(reader.number()?)
+            .ok_or_else(|| "patch offset out of range".to_string())?;
+        // XOR bytes up to a 0, which also counts as a byte.
+        loop {
+            let x = reader.byte()?;
+            if x == 0 {
+                pos = pos.saturating_add(1);
+                break;
+            }
+            if let Some(byte) = out.get_mut(pos) {
+                *byte ^= x;
+            }
+            pos = pos.saturating_add(1);
+        }
+    }
+    check_footer(patch, rom, &out)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &[u8] = b"Hello, world!";
+
+    /// Encodes a BPS/UPS variable-length integer.
+    fn number(mut n: usize) -> Vec<u8> {
+        let mut out = vec![];
+        loop {
+            let x = (n & 0x7F) as u8;
+            n >>= 7;
+            if n == 0 {
+                out.push(0x80 | x);
+                return out;
+            }
+            out.push(x);
+            n -= 1;
+        }
+    }
+
+    fn add_footer(patch: &mut Vec<u8>, source: &[u8], target: &[u8]) {
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        let crc = crc32(patch);
+        patch.extend_from_slice(&crc.to_le_bytes());
+    }
+
+    fn ips_patch() -> Vec<u8> {
+        let mut patch = b"PATCH".to_vec();
+        // "world" at 7 becomes "NES!!".
+        patch.extend_from_slice(&[0x00, 0x00, 0x07, 0x00, 0x05]);
+        patch.extend_from_slice(b"NES!!");
+        // Three '?' at 13, run-length encoded, past the end of the source.
+        patch.extend_from_slice(&[0x00, 0x00, 0x0D, 0x00, 0x00, 0x00, 0x03, b'?']);
+        patch.extend_from_slice(b"EOF");
+        patch
+    }
+
+    fn bps_patch() -> (Vec<u8>, Vec<u8>) {
+        let target = b"Hello, NESNESworld!".to_vec();
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(number(SOURCE.len()));
+        patch.extend(number(target.len()));
+        patch.extend(number(0));
+        // SourceRead "Hello, ".
+        patch.extend(number(6 << 2));
+        // TargetRead "NES".
+        patch.extend(number((2 << 2) | 1));
+        patch.extend_from_slice(b"NES");
+        // TargetCopy "NES" from target offset 7.
+        patch.extend(number((2 << 2) | 3));
+        patch.extend(number(7 << 1));
+        // SourceCopy "world!" from source offset 7.
+        patch.extend(number((5 << 2) | 2));
+        patch.extend(number(7 << 1));
+        add_footer(&mut patch, SOURCE, &target);
+        (patch, target)
+    }
+
+    fn ups_patch(source: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut patch = b"UPS1".to_vec();
+        patch.extend(number(source.len()));
+        patch.extend(number(target.len()));
+        let byte = |data: &[u8], i: usize| data.get(i).copied().unwrap_or(0);
+        let len = source.len().max(target.len());
+        let (mut i, mut last) = (0, 0);
+        while i < len {
+            if byte(source, i) == byte(target, i) {
+                i += 1;
+                continue;
+            }
+            patch.extend(number(i - last));
+            while i < len && byte(source, i) != byte(target, i) {
+                patch.push(byte(source, i) ^ byte(target, i));
+                i += 1;
+            }
+            patch.push(0);
+            i += 1;
+            last = i;
+        }
+        add_footer(&mut patch, source, target);
+        patch
+    }
+
+    #[test]
+    fn ips() {
+        assert_eq!(
+            apply_patch(SOURCE, &ips_patch()).unwrap(),
+            b"Hello, NES!!!???"
+        );
+    }
+
+    #[test]
+    fn ips_truncate_extension() {
+        let mut patch = ips_patch();
+        patch.extend_from_slice(&[0x00, 0x00, 0x05]);
+        assert_eq!(apply_patch(SOURCE, &patch).unwrap(), b"Hello");
+    }
+
+    #[test]
+    fn ips_truncated() {
+        let patch = ips_patch();
+        for len in [8, 12, patch.len() - 3] {
+            assert_eq!(
+                apply_patch(SOURCE, &patch[..len]),
+                Err("patch is truncated".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn bps() {
+        let (patch, target) = bps_patch();
+        assert_eq!(apply_patch(SOURCE, &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn bps_truncated() {
+        let (patch, _) = bps_patch();
+        assert_eq!(
+            apply_patch(SOURCE, &patch[..10]),
+            Err("patch is truncated".to_string())
+        );
+        assert!(apply_patch(SOURCE, &patch[..patch.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn bps_bad_crc() {
+        let (mut patch, _) = bps_patch();
+        *patch.last_mut().unwrap() ^= 0xFF;
+        assert_eq!(
+            apply_patch(SOURCE, &patch),
+            Err("patch is corrupt (checksum mismatch)".to_string())
+        );
+    }
+
+    #[test]
+    fn bps_wrong_source() {
+        let (patch, _) = bps_patch();
+        let error = apply_patch(b"Hello, World!", &patch).unwrap_err();
+        assert!(
+            error.starts_with("patch is for a different ROM"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn bps_number_overflow() {
+        let mut patch = b"BPS1".to_vec();
+        patch.extend_from_slice(&[0x00; 10]);
+        patch.push(0x80);
+        add_footer(&mut patch, SOURCE, SOURCE);
+        assert_eq!(
+            apply_patch(SOURCE, &patch),
+            Err("patch number overflows".to_string())
+        );
+    }
+
+    #[test]
+    fn ups() {
+        for target in [&b"Hello, NES world!!"[..], b"Jello, world", SOURCE] {
+            assert_eq!(
+                apply_patch(SOURCE, &ups_patch(SOURCE, target)).unwrap(),
+                target
+            );
+        }
+    }
+
+    #[test]
+    fn ups_bad_crc() {
+        let mut patch = ups_patch(SOURCE, b"Hello, NES!");
+        let len = patch.len();
+        patch[len - 5] ^= 0xFF;
+        assert_eq!(
+            apply_patch(SOURCE, &patch),
+            Err("patch is corrupt (checksum mismatch)".to_string())
+        );
+    }
+
+    #[test]
+    fn ups_truncated() {
+        let patch = ups_patch(SOURCE, b"Hello, NES!");
+        assert_eq!(
+            apply_patch(SOURCE, &patch[..8]),
+            Err("patch is truncated".to_string())
+        );
+        assert!(apply_patch(SOURCE, &patch[..patch.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn number_round_trip() {
+        for n in [0, 1, 0x7F, 0x80, 0x407F, 0x4080, 1 << 40, usize::MAX] {
+            let encoded = number(n);
+            assert_eq!(Reader::new(&encoded, 0).number(), Ok(n));
+        }
+    }
+}
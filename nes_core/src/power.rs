@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Palette contents at power-on, as dumped from a real console.
+/// https://wiki.nesdev.com/w/index.php/PPU_power_up_state
+const HARDWARE_PALETTE: [u8; 32] = [
+    0x09, 0x01, 0x00, 0x01, 0x00, 0x02, 0x02, 0x0D, 0x08, 0x10, 0x08, 0x24, 0x00, 0x00, 0x04, 0x2C,
+    0x09, 0x01, 0x34, 0x03, 0x00, 0x04, 0x00, 0x14, 0x08, 0x3A, 0x00, 0x02, 0x00, 0x20, 0x2C, 0x08,
+];
+
+/// Contents of memory when the console is powered on.
+///
+/// Applies to CPU RAM, PPU OAM, palette, and nametables, and cartridge PRG-RAM.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum PowerOnPattern {
+    /// Every byte is $00.
+    #[default]
+    Zero,
+    /// Every byte is $FF.
+    Ones,
+    /// What many consoles actually come up with: alternating runs of four $00 and four $FF
+    /// bytes, and the palette a real PPU was dumped with.
+    Hardware,
+    /// Pseudo-random bytes from the given seed, for flushing out uninitialized reads.
+    Random(u64),
+}
+
+impl PowerOnPattern {
+    pub(crate) fn filler(&self) -> MemoryFiller {
+        let seed = match *self {
+            PowerOnPattern::Random(seed) => seed,
+            _ => 0,
+        };
+        MemoryFiller {
+            pattern: *self,
+            rng: XorShift::new(seed),
+        }
+    }
+}
+
+impl FromStr for PowerOnPattern {
+    type Err = String;
+
+    /// Parses `zero`, `ff`, `hardware`, or `random:<seed>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zero" => Ok(PowerOnPattern::Zero),
+            "ff" => Ok(PowerOnPattern::Ones),
+            "hardware" => Ok(PowerOnPattern::Hardware),
+            _ => match s.strip_prefix("random:") {
+                Some(seed) => seed
+                    .parse()
+                    .map(PowerOnPattern::Random)
+                    .map_err(|_| format!("invalid seed: {}", seed)),
+                None => Err(format!("unknown power-on pattern: {}", s)),
+            },
+        }
+    }
+}
+
+/// Fills each memory in turn. Random patterns keep drawing from the same generator, so
+/// different memories don't get the same bytes, but a seed always gives the same console.
+pub struct MemoryFiller {
+    pattern: PowerOnPattern,
+    rng: XorShift,
+}
+
+impl MemoryFiller {
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        match self.pattern {
+            PowerOnPattern::Zero => buf.iter_mut().for_each(|b| *b = 0),
+            PowerOnPattern::Ones => buf.iter_mut().for_each(|b| *b = 0xFF),
+            PowerOnPattern::Hardware => {
+                for (i, b) in buf.iter_mut().enumerate() {
+                    *b = if i & 0x4 == 0 { 0x00 } else { 0xFF };
+                }
+            }
+            PowerOnPattern::Random(_) => {
+                let rng = &mut self.rng;
+                buf.iter_mut().for_each(|b| *b = rng.next_u32() as u8);
+            }
+        }
+    }
+
+    pub fn fill_palette(&mut self, palette: &mut [u8; 32]) {
+        match self.pattern {
+            PowerOnPattern::Hardware => palette.copy_from_slice(&HARDWARE_PALETTE),
+            _ => {
+                self.fill(palette);
+                // Palette RAM is only 6 bits wide.
+                palette.iter_mut().for_each(|b| *b &= 0x3F);
+            }
+        }
+    }
+}
+
+/// xorshift64*: small, fast, and stable across platforms, so a seed always gives the same RAM.
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> XorShift {
+        // The state must be non-zero.
+        match seed ^ 0x9E37_79B9_7F4A_7C15 {
+            0 => XorShift(1),
+            state => XorShift(state),
+        }
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 32) as u32
+    }
+}
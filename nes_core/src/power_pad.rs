@@ -0,0 +1,77 @@
+use super::input::{DeviceKind, InputDevice};
+use super::ppu::PpuState;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+
+/// The order the buttons (numbered 1-12 as on side B of the mat) are shifted out on D3 and D4.
+const D3_ORDER: [usize; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+const D4_ORDER: [usize; 4] = [4, 3, 12, 8];
+
+/// The Power Pad (Bandai Family Fun Fitness) exercise mat: twelve buttons read through two
+/// shift registers, on D3 and D4.
+/// https://wiki.nesdev.com/w/index.php/Power_Pad
+#[derive(Serialize, Deserialize)]
+pub struct PowerPad {
+    buttons: [bool; 12],
+    shift_d3: u8,
+    shift_d4: u8,
+    strobe: bool,
+}
+
+impl PowerPad {
+    pub fn new() -> PowerPad {
+        PowerPad {
+            buttons: [false; 12],
+            shift_d3: 0,
+            shift_d4: 0,
+            strobe: false,
+        }
+    }
+
+    /// Sets which buttons are stepped on. Index 0 is button 1.
+    pub fn set_state(&mut self, buttons: [bool; 12]) {
+        self.buttons = buttons;
+    }
+
+    fn reload(&mut self) {
+        let buttons = self.buttons;
+        let pressed = |n: &usize| buttons[n - 1] as u8;
+        self.shift_d3 = D3_ORDER
+            .iter()
+            .enumerate()
+            .fold(0, |acc, (i, n)| acc | pressed(n) << i);
+        // The last four bits are always 1.
+        self.shift_d4 = D4_ORDER
+            .iter()
+            .enumerate()
+            .fold(0xF0, |acc, (i, n)| acc | pressed(n) << i);
+    }
+}
+
+impl InputDevice for PowerPad {
+    fn read(&mut self, _addr: u16, _ppu: &PpuState) -> u8 {
+        if self.strobe {
+            self.reload();
+        }
+        let data = ((self.shift_d3 & 0x1) << 3) | ((self.shift_d4 & 0x1) << 4);
+        self.shift_d3 = (self.shift_d3 >> 1) | 0x80;
+        self.shift_d4 = (self.shift_d4 >> 1) | 0x80;
+        data
+    }
+
+    fn write(&mut self, data: u8) {
+        let strobe = data & 0x1 > 0;
+        if strobe || self.strobe {
+            self.reload();
+        }
+        self.strobe = strobe;
+    }
+
+    fn get_kind(&self) -> DeviceKind {
+        DeviceKind::PowerPad
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -1,20 +1,21 @@
 use super::cpu;
-use super::nes::{State, FRAME_DEPTH, FRAME_SIZE, FRAME_WIDTH};
+use super::hooks::{self, HookEvent};
+use super::nes::{State, FRAME_HEIGHT, FRAME_PIXELS, FRAME_SIZE, FRAME_WIDTH};
+use super::region::Region;
 use serde::{Deserialize, Serialize};
 use serde_big_array::big_array;
 
-const COLORS: [u32; 64] = [
-    0x545454, 0x001e74, 0x081090, 0x300088, 0x440064, 0x5c0030, 0x540400, 0x3c1800, 0x202a00,
-    0x083a00, 0x004000, 0x003c00, 0x00323c, 0x000000, 0x000000, 0x000000, 0x989698, 0x084cc4,
-    0x3032ec, 0x5c1ee4, 0x8814b0, 0xa01464, 0x982220, 0x783c00, 0x545a00, 0x287200, 0x087c00,
-    0x007628, 0x006678, 0x000000, 0x000000, 0x000000, 0xeceeec, 0x4c9aec, 0x787cec, 0xb062ec,
-    0xe454ec, 0xec58b4, 0xec6a64, 0xd48820, 0xa0aa00, 0x74c400, 0x4cd020, 0x38cc6c, 0x38b4cc,
-    0x3c3c3c, 0x000000, 0x000000, 0xeceeec, 0xa8ccec, 0xbcbcec, 0xd4b2ec, 0xecaeec, 0xecaed4,
-    0xecb4b0, 0xe4c490, 0xccd278, 0xb4de78, 0xa8e290, 0x98e2b4, 0xa0d6e4, 0xa0a2a0, 0x000000,
-    0x000000,
-];
+big_array! { BigArray; 240, 256, 61440, 245760 }
 
-big_array! { BigArray; 256, 245760 }
+/// How long (in dots) a bit of the I/O latch holds its value without being refreshed: ~600 ms.
+const LATCH_DECAY_DOTS: u64 = 3_200_000;
+
+/// The PPU's progress is tracked in fractions of a CPU cycle, so a write can land part way
+/// through the cycle it's made in.
+const CLOCKS_PER_CPU_CYCLE: u64 = 12;
+/// How far into its cycle a CPU write reaches the PPU's registers: the data is only on the bus
+/// for the second half of the cycle.
+const WRITE_CLOCK: u64 = 7;
 
 #[derive(Copy, Clone, Serialize, Deserialize)]
 struct SpriteBufferData {
@@ -43,16 +44,30 @@ pub struct PpuState {
     pub frames: u64,
     cycles: u64,
 
-    // Last CPU cycle that we emulated at.
-    last_cpu_cycle: u64,
+    // How far the PPU has been run, in CPU cycles times CLOCKS_PER_CPU_CYCLE.
+    clock: u64,
 
     //#[serde(skip)]
     #[serde(with = "BigArray")]
     pub frame_buffer: [u8; FRAME_SIZE],
+    /// The frame as the PPU outputs it: emphasis (red in bit 6, green in bit 7, blue in bit 8)
+    /// and the 6-bit palette index. `frame_buffer` is converted from this after each frame.
+    /// Left out of save states, which already have the finished frame in `frame_buffer`.
+    #[serde(skip, default = "empty_index_buffer")]
+    pub index_buffer: [u16; FRAME_PIXELS],
+    /// Where each line starts in the NTSC color subcarrier's cycle, in thirds. Each dot is 8
+    /// of the 12 phases of the subcarrier.
+    /// https://wiki.nesdev.com/w/index.php/NTSC_video
+    #[serde(with = "BigArray")]
+    pub scanline_phase: [u8; FRAME_HEIGHT],
 
-    is_rendering: bool,
+    // Writes to PPUCTRL, PPUMASK, PPUSCROLL, and PPUADDR are ignored until the end of the first
+    // vblank after power-on or reset.
+    warmup: bool,
     data_buffer: u8,
+    // The I/O latch ("PPU open bus"), and the dot on which each of its bits was last driven.
     latch: u8,
+    latch_refreshed: [u64; 8],
     sprite_overflow: u8,
     sprite0_hit: bool,
     vblank: u8,
@@ -61,17 +76,37 @@ pub struct PpuState {
     #[serde(with = "BigArray")]
     pub oam_1: [u8; 256],
     oam_2: [u8; 32],
-    sprite_eval_n: usize,
-    sprite_eval_m: usize,
+    // Sprite evaluation walks primary OAM with oam_addr, as the hardware does.
+    // https://wiki.nesdev.com/w/index.php/PPU_sprite_evaluation
+    // Bytes left to copy of a sprite that's in range, or to read past an overflowing sprite.
+    sprite_eval_copy: usize,
+    // All 64 sprites have been looked at.
+    sprite_eval_done: bool,
+    // The OAM bus: what $2004 reads during rendering.
     sprite_eval_read: u8,
     sprite_eval_scanline_count: usize,
     sprite_eval_has_sprite0: bool, // Whether sprite0 is at oam_2[0]
     #[serde(with = "BigArray")]
     sprite_buffer: [SpriteBufferData; 256], // Sprite scanline buffer.
+    /// Also draw the sprites past the eight per line the hardware can show.
+    #[serde(skip)]
+    pub unlimited_sprites: bool,
 
     pub palette: [u8; 32],
-    bg_data: [u8; 24],
-    bg_data_index: usize,
+    // The high byte of a sprite's pattern is fetched two dots after the low byte.
+    sprite_pattern_lo: u8,
+
+    // Background fetches: the latches each tile's bytes are fetched into, and the shift
+    // registers the pixels come out of. Tiles are loaded into the low bytes, and shifted up.
+    // https://wiki.nesdev.com/w/index.php/PPU_rendering
+    bg_next_tile: u8,
+    bg_next_attribute: u8,
+    bg_next_lo: u8,
+    bg_next_hi: u8,
+    bg_shift_lo: u16,
+    bg_shift_hi: u16,
+    bg_shift_attribute_lo: u16,
+    bg_shift_attribute_hi: u16,
 
     // Scrolling registers
     v: u16,
@@ -105,26 +140,37 @@ impl PpuState {
             tick: 0,
             frames: 0,
             cycles: 0,
-            last_cpu_cycle: 7,
+            clock: 7 * CLOCKS_PER_CPU_CYCLE,
             frame_buffer: [0; FRAME_SIZE],
-            is_rendering: false,
+            index_buffer: [0; FRAME_PIXELS],
+            scanline_phase: [0; FRAME_HEIGHT],
+            warmup: true,
             data_buffer: 0,
             latch: 0,
+            latch_refreshed: [0; 8],
             sprite_overflow: 0,
             sprite0_hit: false,
             vblank: 0,
             oam_addr: 0,
             oam_1: [0; 256],
             oam_2: [0; 32],
-            sprite_eval_n: 0,
-            sprite_eval_m: 0,
+            sprite_eval_copy: 0,
+            sprite_eval_done: false,
             sprite_eval_read: 0,
             sprite_eval_scanline_count: 0,
             sprite_eval_has_sprite0: false,
             sprite_buffer: [SpriteBufferData::default(); 256],
+            unlimited_sprites: false,
             palette: [0; 32],
-            bg_data: [0; 24],
-            bg_data_index: 0,
+            sprite_pattern_lo: 0,
+            bg_next_tile: 0,
+            bg_next_attribute: 0,
+            bg_next_lo: 0,
+            bg_next_hi: 0,
+            bg_shift_lo: 0,
+            bg_shift_hi: 0,
+            bg_shift_attribute_lo: 0,
+            bg_shift_attribute_hi: 0,
             v: 0,
             t: 0,
             x: 0,
@@ -147,53 +193,77 @@ impl PpuState {
     }
 }
 
+fn empty_index_buffer() -> [u16; FRAME_PIXELS] {
+    [0; FRAME_PIXELS]
+}
+
+/// Runs the PPU up to the start of the CPU's current cycle.
 pub fn catch_up(s: &mut State) {
-    let cpu_cycles = s.cpu.cycles - s.ppu.last_cpu_cycle;
-    emulate(s, cpu_cycles * 3);
+    run_to(s, s.cpu.cycles * CLOCKS_PER_CPU_CYCLE);
+}
+
+/// Runs the PPU up to where a write in the CPU's current cycle lands. The CPU only counts the
+/// cycle after the access, so without this, writes would take effect up to a CPU cycle early.
+fn catch_up_to_write(s: &mut State) {
+    run_to(s, s.cpu.cycles * CLOCKS_PER_CPU_CYCLE + WRITE_CLOCK);
+}
+
+fn run_to(s: &mut State, clock: u64) {
+    if clock <= s.ppu.clock {
+        return;
+    }
+    // Dots are counted from the start, so fractions of a dot aren't lost along the way.
+    let (num, den) = s.region.ppu_clock_ratio();
+    let dots_at = |clock: u64| clock * num / (den * CLOCKS_PER_CPU_CYCLE);
+    let dots = dots_at(clock) - dots_at(s.ppu.clock);
+    s.ppu.clock = clock;
+    emulate(s, dots);
 }
 
 pub fn emulate(s: &mut State, cycles: u64) {
-    s.ppu.last_cpu_cycle = s.cpu.cycles;
+    let prerender_scanline = s.region.scanlines() - 1;
+    let vblank_scanline = s.region.vblank_scanline();
 
     let mut cycles_left = cycles;
     while cycles_left > 0 {
+        if s.hooks.any_dot {
+            let (scanline, dot) = (s.ppu.scanline, s.ppu.tick);
+            hooks::run(s, HookEvent::Dot { scanline, dot });
+        }
         let rendering_enabled = s.ppu.flag_render_sprites || s.ppu.flag_render_background;
 
-        if s.ppu.scanline == 261 {
+        if s.ppu.scanline == prerender_scanline {
             // Pre-render.
             if s.ppu.tick == 1 {
                 s.ppu.sprite0_hit = false;
+                s.ppu.sprite_overflow = 0;
                 s.ppu.vblank = 0;
-                s.ppu.is_rendering = true;
+                s.ppu.warmup = false;
             }
-            if s.ppu.tick == 304 && rendering_enabled {
-                // XXX:
+            if s.ppu.tick >= 280 && s.ppu.tick <= 304 && rendering_enabled {
                 // copy vertical scroll bits
                 // v: IHGF.ED CBA..... = t: IHGF.ED CBA.....
                 s.ppu.v = (s.ppu.v & 0x841F) | (s.ppu.t & 0x7BE0);
             }
 
-            // XXX: This should make the same memory fetches (tiles and sprites) as visible lines.
+            if rendering_enabled && s.ppu.tick >= 1 && s.ppu.tick <= 8 && s.ppu.oam_addr >= 8 {
+                // When rendering starts with OAMADDR at 8 or more, the 2C02 copies the 8 bytes
+                // at OAMADDR & 0xF8 over the first 8 bytes of OAM.
+                // https://wiki.nesdev.com/w/index.php/PPU_registers#OAMADDR
+                let i = (s.ppu.tick - 1) as usize;
+                s.ppu.oam_1[i] = s.ppu.oam_1[(s.ppu.oam_addr & 0xF8) + i];
+            }
         }
 
-        if (s.ppu.scanline <= 239 || s.ppu.scanline == 261) && rendering_enabled {
+        if (s.ppu.scanline <= 239 || s.ppu.scanline == prerender_scanline) && rendering_enabled {
             // Pre-render and visible scanlines.
-            if (s.ppu.tick >= 1 && s.ppu.tick <= 256) || (s.ppu.tick >= 321 && s.ppu.tick <= 336) {
-                s.ppu.bg_data_index += 1;
-                s.ppu.bg_data_index %= s.ppu.bg_data.len();
-
-                if s.ppu.tick & 0x7 == 1 {
-                    fetch_tile(s);
-                }
+            if (s.ppu.tick >= 2 && s.ppu.tick <= 257) || (s.ppu.tick >= 322 && s.ppu.tick <= 337) {
+                shift_background(&mut s.ppu);
             }
-        }
-
-        if s.ppu.scanline < 240 && rendering_enabled {
-            if s.ppu.tick >= 1 && s.ppu.tick <= 256 {
+            if s.ppu.scanline < 240 && s.ppu.tick >= 1 && s.ppu.tick <= 256 {
                 render_pixel(s);
             }
-
-            sprite_evaluation(s);
+            fetch_background(s);
 
             // Update scrolling.
             if s.ppu.tick == 256 {
@@ -202,37 +272,56 @@ pub fn emulate(s: &mut State, cycles: u64) {
                 // copy horizontal bits from t to v
                 // v: ....F.. ...EDCBA = t: ....F.. ...EDCBA
                 s.ppu.v = (s.ppu.v & 0xFBE0) | (s.ppu.t & 0x41F);
-            } else if ((s.ppu.tick >= 321 && s.ppu.tick <= 336)
-                || (s.ppu.tick >= 1 && s.ppu.tick <= 256))
-                && (s.ppu.tick % 8 == 0)
-            {
-                increment_scroll_x(&mut s.ppu);
             }
         }
 
-        // Scanline 240 (post-render) is idle.
+        if s.ppu.scanline < 240 && rendering_enabled {
+            sprite_evaluation(s);
+        } else if s.ppu.scanline == prerender_scanline
+            && rendering_enabled
+            && s.ppu.tick >= 257
+            && s.ppu.tick <= 320
+        {
+            // The sprite fetches still happen, but nothing was evaluated for them.
+            fetch_sprites(s);
+        }
+
+        // Scanline 240 (post-render) is idle, as is the rest of vblank.
+
+        if s.ppu.scanline < 240 && !rendering_enabled && s.ppu.tick >= 1 && s.ppu.tick <= 256 {
+            render_backdrop(s);
+        }
 
-        if s.ppu.scanline == 241 && s.ppu.tick == 1 {
+        if s.ppu.scanline == vblank_scanline && s.ppu.tick == 1 {
             // Start of vblank.
             if s.ppu.flag_generate_nmi {
                 s.cpu.pending_interrupt = cpu::InterruptKind::NMI;
             }
-            s.ppu.is_rendering = false;
             s.ppu.vblank = 1;
             s.ppu.frames += 1;
+            if s.hooks.any_vblank {
+                hooks::run(s, HookEvent::VblankStart);
+            }
+        }
+        if s.hooks.any_irq {
+            hooks::check_mapper_irq(s);
         }
 
         // Increment counters.
         s.ppu.cycles += 1;
         s.ppu.tick += 1;
-        if s.ppu.scanline == 261 && (s.ppu.frames & 1 > 0) && s.ppu.tick == 340 && rendering_enabled
+        if s.ppu.scanline == prerender_scanline
+            && (s.ppu.frames & 1 > 0)
+            && s.ppu.tick == 340
+            && rendering_enabled
+            && s.region.skips_odd_frame_dot()
         {
             s.ppu.tick += 1;
         }
         if s.ppu.tick == 341 {
             s.ppu.tick = 0;
             s.ppu.scanline += 1;
-            if s.ppu.scanline > 261 {
+            if s.ppu.scanline > prerender_scanline {
                 s.ppu.scanline = 0;
             }
         }
@@ -242,132 +331,239 @@ pub fn emulate(s: &mut State, cycles: u64) {
 
 fn sprite_evaluation(s: &mut State) {
     match s.ppu.tick {
-        1 => {
-            // Ticks 1-64: clear secondary OAM.
-            // But secondary OAM is fully internal state, so just do it all at once.
-            for i in 0..32 {
-                s.ppu.oam_2[i] = 0xFF;
+        1..=64 => {
+            // Ticks 1-64: clear secondary OAM, a byte every other tick. Reads of OAM see $FF.
+            s.ppu.sprite_eval_read = 0xFF;
+            if s.ppu.tick & 0x1 == 0 {
+                s.ppu.oam_2[(s.ppu.tick / 2 - 1) as usize] = 0xFF;
+            }
+            if s.ppu.tick == 64 {
+                s.ppu.sprite_eval_copy = 0;
+                s.ppu.sprite_eval_done = false;
+                s.ppu.sprite_eval_scanline_count = 0;
+                s.ppu.sprite_eval_has_sprite0 = false;
             }
-
-            s.ppu.sprite_eval_n = 0;
-            s.ppu.sprite_eval_m = 0;
-            s.ppu.sprite_eval_scanline_count = 0;
-            s.ppu.sprite_eval_has_sprite0 = false;
         }
-        65..=256 if (s.ppu.sprite_eval_n < 64 && s.ppu.sprite_eval_scanline_count < 8) => {
-            // Ticks 65-256: fetch sprite data from primary OAM into secondary OAM.
+        65..=256 => {
+            // Ticks 65-256: copy the sprites in range from primary OAM into secondary OAM.
             if s.ppu.tick & 0x1 == 1 {
-                // Primary OAM read
-                let index = s.ppu.sprite_eval_n * 4 + s.ppu.sprite_eval_m;
-                s.ppu.sprite_eval_read = s.ppu.oam_1[index];
+                s.ppu.sprite_eval_read = s.ppu.oam_1[s.ppu.oam_addr];
             } else {
-                // Secondary OAM write
-                let oam_2_addr = 4 * s.ppu.sprite_eval_scanline_count + s.ppu.sprite_eval_m;
-                s.ppu.oam_2[oam_2_addr] = s.ppu.sprite_eval_read;
-                match s.ppu.sprite_eval_m {
-                    0 => {
-                        // Y coordinate: is this sprite in range?
-                        let sprite_height = (8 << s.ppu.flag_sprite_size) as u16;
-                        let top = s.ppu.sprite_eval_read as u16;
-                        let bottom = top + sprite_height;
-                        if s.ppu.scanline >= top && s.ppu.scanline < bottom {
-                            // Sprite in range.
-                            s.ppu.sprite_eval_m += 1;
-                        } else {
-                            // Sprite not in range.
-                            s.ppu.sprite_eval_n += 1;
-                        }
-                    }
-                    3 => {
-                        if s.ppu.sprite_eval_n == 0 {
-                            s.ppu.sprite_eval_has_sprite0 = true;
-                        }
-                        s.ppu.sprite_eval_n += 1;
-                        s.ppu.sprite_eval_m = 0;
-                        s.ppu.sprite_eval_scanline_count += 1;
-                    }
-                    _ => {
-                        s.ppu.sprite_eval_m += 1;
-                    }
-                }
+                evaluate_sprite(s);
             }
-            // TODO: do sprite overflow flag
         }
-        256 => {
-            // Internal: set up scanline buffer state.
-            for i in 0..256 {
-                s.ppu.sprite_buffer[i] = SpriteBufferData::default();
+        257..=320 => fetch_sprites(s),
+        _ => {
+            s.ppu.sprite_eval_read = s.ppu.oam_2[0];
+        }
+    }
+}
+
+/// Handles the byte read from primary OAM on the previous tick.
+fn evaluate_sprite(s: &mut State) {
+    let ppu = &mut s.ppu;
+    let data = ppu.sprite_eval_read;
+    let sprite_height = (8 << ppu.flag_sprite_size) as u16;
+    let in_range = ppu.scanline >= data as u16 && ppu.scanline < data as u16 + sprite_height;
+    // Moves oam_addr along, and stops once it wraps past the last sprite.
+    let advance = |ppu: &mut PpuState, addr: usize| {
+        if addr > 0xFF {
+            ppu.sprite_eval_done = true;
+        }
+        ppu.oam_addr = addr & 0xFF;
+    };
+
+    if ppu.sprite_eval_done {
+        // Keep trying (and failing) to copy sprites' Y coordinates.
+        let addr = ppu.oam_addr + 4;
+        ppu.oam_addr = addr & 0xFF;
+    } else if ppu.sprite_eval_scanline_count < 8 {
+        let oam_2_addr = 4 * ppu.sprite_eval_scanline_count + (4 - ppu.sprite_eval_copy) % 4;
+        ppu.oam_2[oam_2_addr] = data;
+        if ppu.sprite_eval_copy > 0 {
+            ppu.sprite_eval_copy -= 1;
+            if ppu.sprite_eval_copy == 0 {
+                ppu.sprite_eval_scanline_count += 1;
+            }
+            let addr = ppu.oam_addr + 1;
+            advance(ppu, addr);
+        } else if in_range {
+            // Whichever sprite is looked at first counts as sprite 0, even if OAMADDR didn't
+            // start at 0.
+            if ppu.tick == 66 {
+                ppu.sprite_eval_has_sprite0 = true;
             }
+            ppu.sprite_eval_copy = 3;
+            ppu.oam_addr = (ppu.oam_addr + 1) & 0xFF;
+        } else {
+            let addr = ppu.oam_addr + 4;
+            advance(ppu, addr);
+        }
+    } else if ppu.sprite_eval_copy > 0 {
+        // Secondary OAM is full; the write becomes a read of it.
+        ppu.sprite_eval_read = ppu.oam_2[0];
+        ppu.sprite_eval_copy -= 1;
+        let addr = ppu.oam_addr + 1;
+        advance(ppu, addr);
+        if ppu.sprite_eval_copy == 0 {
+            ppu.sprite_eval_done = true;
         }
-        257..=320 if (s.ppu.tick & 0x7 == 0) => {
-            // Ticks 257-320: fetch selected sprite data from pattern tables.
-            let n = ((s.ppu.tick - 257) / 8) as usize;
+    } else if in_range {
+        // A ninth sprite.
+        ppu.sprite_overflow = 1;
+        ppu.sprite_eval_copy = 3;
+        let addr = ppu.oam_addr + 1;
+        advance(ppu, addr);
+    } else {
+        // The hardware bug: moving on to the next sprite also moves to its next byte, without
+        // carrying into the sprite number. Later sprites' tile numbers, attributes and X
+        // coordinates get checked as Y coordinates.
+        let addr = ((ppu.oam_addr & 0xFC) + 4) | ((ppu.oam_addr + 1) & 0x3);
+        advance(ppu, addr);
+    }
+}
 
-            let mut y_pos = s.ppu.oam_2[n * 4 + 0];
-            let mut tile = s.ppu.oam_2[n * 4 + 1];
-            let mut attribute = s.ppu.oam_2[n * 4 + 2];
-            let mut x_pos = s.ppu.oam_2[n * 4 + 3];
+/// Ticks 257-320: the fetches for the next line's sprites. Each of the 8 sprites in secondary
+/// OAM takes 8 dots: two garbage nametable fetches, then the low and high bytes of its
+/// pattern. Each fetch puts its address out on the first of its two dots.
+fn fetch_sprites(s: &mut State) {
+    s.ppu.oam_addr = 0;
+    // The sprite fetches read secondary OAM: Y, tile, attributes, then X four times.
+    let n = ((s.ppu.tick - 257) / 8) as usize;
+    let byte = usize::min(((s.ppu.tick - 257) % 8) as usize, 3);
+    s.ppu.sprite_eval_read = s.ppu.oam_2[n * 4 + byte];
 
-            if n >= s.ppu.sprite_eval_scanline_count {
-                // Dummy reads from pattern table.
-                // "y-pos" is 0xFF (or sprite 63's Y for first non-existent sprite)
-                // but that doesn't work well for computing the address to read in the
-                // pattern table, so just set it to the current scanline.
-                y_pos = s.ppu.scanline as u8;
-                tile = 0xFF;
-                attribute = 0xFF;
-                x_pos = 0xFF;
+    match (s.ppu.tick - 257) % 8 {
+        0 | 2 => {
+            if s.ppu.tick == 257 {
+                // Internal: set up scanline buffer state.
+                for i in 0..256 {
+                    s.ppu.sprite_buffer[i] = SpriteBufferData::default();
+                }
             }
+            s.ppu_peek(0x2000 | (s.ppu.v & 0x0FFF));
+        }
+        4 => {
+            let pattern_addr = fetched_sprite_pattern_addr(s, n);
+            s.ppu.sprite_pattern_lo = s.ppu_peek(pattern_addr);
+        }
+        6 => {
+            let pattern_addr = fetched_sprite_pattern_addr(s, n);
+            let hi = s.ppu_peek(pattern_addr | 0x8);
+            let x_pos = s.ppu.oam_2[n * 4 + 3];
+            // Don't draw non-existent sprites.
+            if n < s.ppu.sprite_eval_scanline_count && s.ppu.scanline < 240 && x_pos != 0xFF {
+                let attribute = s.ppu.oam_2[n * 4 + 2];
+                let lo = s.ppu.sprite_pattern_lo;
+                let sprite0 = s.ppu.sprite_eval_has_sprite0 && (n == 0);
+                draw_sprite(&mut s.ppu, n, x_pos, attribute, lo, hi, sprite0);
+            }
+        }
+        _ => {}
+    }
 
-            let mut sprite_table = s.ppu.flag_sprite_table_addr;
-            let mut tile_row = s.ppu.scanline - (y_pos as u16);
-            let flip_vertical = attribute & 0x80 > 0;
+    if s.ppu.tick == 320 && s.ppu.unlimited_sprites && s.ppu.scanline < 240 {
+        draw_extra_sprites(s);
+    }
+}
 
-            if s.ppu.flag_sprite_size > 0 {
-                // 16 height
-                sprite_table = tile & 0x1;
-                tile &= 0xFE;
+/// The pattern address fetched for sprite `n` of secondary OAM.
+fn fetched_sprite_pattern_addr(s: &State, n: usize) -> u16 {
+    if n >= s.ppu.sprite_eval_scanline_count || s.ppu.scanline >= 240 {
+        // Dummy reads from pattern table, of tile $FF.
+        // "y-pos" is 0xFF (or sprite 63's Y for first non-existent sprite)
+        // but that doesn't work well for computing the address to read in the
+        // pattern table, so just use the first row.
+        return sprite_pattern_addr(&s.ppu, 0, 0xFF, 0xFF);
+    }
+    let y_pos = s.ppu.oam_2[n * 4 + 0];
+    let tile = s.ppu.oam_2[n * 4 + 1];
+    let attribute = s.ppu.oam_2[n * 4 + 2];
+    sprite_pattern_addr(&s.ppu, s.ppu.scanline - (y_pos as u16), tile, attribute)
+}
 
-                tile |= ((tile_row >= 8) ^ flip_vertical) as u8;
-                tile_row &= 0x7;
-            }
+/// Draws the sprites in range past the first eight behind the others, for showing games
+/// without flicker. Nothing the game can see changes: the hardware's evaluation has already
+/// set the overflow flag, and these sprites never count for sprite 0 hits.
+fn draw_extra_sprites(s: &mut State) {
+    if s.ppu.sprite_eval_scanline_count < 8 {
+        return;
+    }
+    let sprite_height = (8 << s.ppu.flag_sprite_size) as u16;
+    let mut in_range = 0;
+    for n in 0..64 {
+        let y_pos = s.ppu.oam_1[n * 4];
+        if s.ppu.scanline < y_pos as u16 || s.ppu.scanline >= y_pos as u16 + sprite_height {
+            continue;
+        }
+        in_range += 1;
+        if in_range <= 8 {
+            continue;
+        }
+        let tile = s.ppu.oam_1[n * 4 + 1];
+        let attribute = s.ppu.oam_1[n * 4 + 2];
+        let x_pos = s.ppu.oam_1[n * 4 + 3];
+        // Read the patterns without the fetches a mapper could notice.
+        let tile_row = s.ppu.scanline - (y_pos as u16);
+        let pattern_addr = sprite_pattern_addr(&s.ppu, tile_row, tile, attribute);
+        let lo = s.mapper.peek_chr(pattern_addr);
+        let hi = s.mapper.peek_chr(pattern_addr | 0x8);
+        draw_sprite(&mut s.ppu, n, x_pos, attribute, lo, hi, false);
+    }
+}
 
-            if flip_vertical {
-                tile_row = 7 - tile_row;
-            }
+/// The address of the low byte of row `tile_row` (counting down from the sprite's top) of a
+/// sprite in the pattern tables.
+fn sprite_pattern_addr(ppu: &PpuState, tile_row: u16, tile: u8, attribute: u8) -> u16 {
+    let mut tile = tile;
+    let mut sprite_table = ppu.flag_sprite_table_addr;
+    let mut tile_row = tile_row;
+    let flip_vertical = attribute & 0x80 > 0;
 
-            let pattern_addr = 0 | tile_row | ((tile as u16) << 4) | ((sprite_table as u16) << 12);
-            let lo = s.ppu_peek(pattern_addr);
-            let hi = s.ppu_peek(pattern_addr | 0x8);
+    if ppu.flag_sprite_size > 0 {
+        // 16 height
+        sprite_table = tile & 0x1;
+        tile &= 0xFE;
 
-            // Don't draw non-existent sprites.
-            if x_pos == 0xFF {
-                return;
-            }
+        tile |= ((tile_row >= 8) ^ flip_vertical) as u8;
+        tile_row &= 0x7;
+    }
 
-            let flip_horizontal = attribute & 0x40 > 0;
-            for i in 0..8 {
-                let x_off = if flip_horizontal { i } else { 7 - i };
-                let buf_x = (x_pos as usize) + (x_off as usize);
-                if buf_x >= 256 {
-                    continue;
-                }
-                let mut entry = &mut s.ppu.sprite_buffer[buf_x];
-                if entry.id == 0xFF || (entry.color & 0b11) == 0 {
-                    // No sprite is here yet, so put this one.
-                    entry.id = n as u8;
-                    entry.color = 0b10000
-                        | (((lo & (1 << i)) > 0) as u8) << 0
-                        | (((hi & (1 << i)) > 0) as u8) << 1
-                        | (attribute & 0b11) << 2;
-                    entry.priority = (attribute & 0b00100000) == 0;
-                    entry.sprite0 = s.ppu.sprite_eval_has_sprite0 && (n == 0);
-                }
-            }
+    if flip_vertical {
+        tile_row = 7 - tile_row;
+    }
+
+    0 | tile_row | ((tile as u16) << 4) | ((sprite_table as u16) << 12)
+}
 
-            // Add to buffer.
+/// Puts a sprite's row into the scanline buffer, behind any sprites already there.
+fn draw_sprite(
+    ppu: &mut PpuState,
+    n: usize,
+    x_pos: u8,
+    attribute: u8,
+    lo: u8,
+    hi: u8,
+    sprite0: bool,
+) {
+    let flip_horizontal = attribute & 0x40 > 0;
+    for i in 0..8 {
+        let x_off = if flip_horizontal { i } else { 7 - i };
+        let buf_x = (x_pos as usize) + (x_off as usize);
+        if buf_x >= 256 {
+            continue;
+        }
+        let entry = &mut ppu.sprite_buffer[buf_x];
+        if entry.id == 0xFF || (entry.color & 0b11) == 0 {
+            // No sprite is here yet, so put this one.
+            entry.id = n as u8;
+            entry.color = 0b10000
+                | (((lo & (1 << i)) > 0) as u8) << 0
+                | (((hi & (1 << i)) > 0) as u8) << 1
+                | (attribute & 0b11) << 2;
+            entry.priority = (attribute & 0b00100000) == 0;
+            entry.sprite0 = sprite0;
         }
-        _ => {}
     }
 }
 
@@ -400,9 +596,12 @@ fn increment_scroll_x(ppu: &mut PpuState) {
 
 fn render_pixel(s: &mut State) {
     let x = (s.ppu.tick - 1) as usize;
-    let y = s.ppu.scanline as usize;
-    let bg_index = (s.ppu.bg_data_index + (s.ppu.x as usize)) % 24;
-    let mut bg_pixel = s.ppu.bg_data[bg_index];
+    let bit = 15 - s.ppu.x;
+    let bit_of = |shift: u16| ((shift >> bit) & 0x1) as u8;
+    let mut bg_pixel = bit_of(s.ppu.bg_shift_lo)
+        | bit_of(s.ppu.bg_shift_hi) << 1
+        | bit_of(s.ppu.bg_shift_attribute_lo) << 2
+        | bit_of(s.ppu.bg_shift_attribute_hi) << 3;
     let mut sprite_pixel = s.ppu.sprite_buffer[x].color;
 
     if x < 8 {
@@ -432,110 +631,231 @@ fn render_pixel(s: &mut State) {
         }
     };
 
-    let pixel = COLORS[s.ppu.palette[(col & 0x1F) as usize] as usize];
-    let frame = &mut s.ppu.frame_buffer;
-    let i = ((y * FRAME_WIDTH) + x) * FRAME_DEPTH;
-    frame[i + 0] = ((pixel & 0xFF0000) >> 16) as u8;
-    frame[i + 1] = ((pixel & 0x00FF00) >> 8) as u8;
-    frame[i + 2] = ((pixel & 0x0000FF) >> 0) as u8;
-    frame[i + 3] = 255;
+    let index = s.ppu.palette[(col & 0x1F) as usize];
+    output_pixel(s, index);
 }
 
-fn fetch_tile(s: &mut State) {
-    let nt_addr = 0x2000 | (s.ppu.v & 0x0FFF);
-    let nt_data = s.ppu_peek(nt_addr) as u16;
-    let at_addr = 0x23C0 | (s.ppu.v & 0x0C00) | ((s.ppu.v >> 4) & 0x38) | ((s.ppu.v >> 2) & 0x07);
-    let at_data = s.ppu_peek(at_addr) as u16;
+/// With rendering off, the PPU outputs the backdrop color, unless v points into the palette:
+/// then it outputs the color there (the "background color hack").
+/// https://wiki.nesdev.com/w/index.php/PPU_palettes#The_background_palette_hack
+fn render_backdrop(s: &mut State) {
+    let index = if s.ppu.v & 0x3F00 == 0x3F00 {
+        s.ppu_peek(s.ppu.v)
+    } else {
+        s.ppu.palette[0]
+    };
+    output_pixel(s, index);
+}
 
-    // process attribute data to select correct tile
-    let at_data = ((at_data >> (((s.ppu.v >> 4) & 4) | (s.ppu.v & 2))) & 3) << 2;
+/// Puts a palette entry at the current dot of the frame, with grayscale and emphasis applied.
+fn output_pixel(s: &mut State, index: u8) {
+    let x = (s.ppu.tick - 1) as usize;
+    let y = s.ppu.scanline as usize;
+    let mut index = index & 0x3F;
+    if s.ppu.flag_grayscale {
+        // Only the gray column is left.
+        index &= 0x30;
+    }
+    s.ppu.index_buffer[(y * FRAME_WIDTH) + x] = ((emphasis(s) as u16) << 6) | index as u16;
+    if x == 0 {
+        s.ppu.scanline_phase[y] = (s.ppu.cycles % 3) as u8;
+    }
+}
 
-    let pattern_addr: u16 = 0
-        | ((s.ppu.v >> 12) & 0x7)
-        | nt_data << 4
-        | (s.ppu.flag_background_table_addr as u16) << 12;
+/// Converts the indexed frame to RGBA with the current palette.
+pub fn update_frame_buffer(s: &mut State) {
+    s.rgb_palette
+        .to_rgba(&s.ppu.index_buffer, &mut s.ppu.frame_buffer);
+}
 
-    let mut pattern_lo = s.ppu_peek(pattern_addr) as u16;
-    let mut pattern_hi = s.ppu_peek(pattern_addr | 0x8) as u16;
+/// Which of red (bit 0), green (bit 1), and blue (bit 2) are emphasized. PAL PPUs swap the
+/// red and green bits.
+/// https://wiki.nesdev.com/w/index.php/PPU_registers#Color_Control
+fn emphasis(s: &State) -> u8 {
+    let ppu = &s.ppu;
+    let (red, green) = match s.region {
+        Region::Ntsc => (ppu.flag_emphasize_red, ppu.flag_emphasize_green),
+        Region::Pal | Region::Dendy => (ppu.flag_emphasize_green, ppu.flag_emphasize_red),
+    };
+    (red as u8) | ((green as u8) << 1) | ((ppu.flag_emphasize_blue as u8) << 2)
+}
 
-    for i in 0..8 {
-        let pixel_data = at_data | (pattern_lo & 0x1) | ((pattern_hi & 0x1) << 1);
-        pattern_lo >>= 1;
-        pattern_hi >>= 1;
-        let ind = (s.ppu.bg_data_index + 24 - 1 - i) % s.ppu.bg_data.len();
-        s.ppu.bg_data[ind] = pixel_data as u8;
+/// One dot of the background fetches on a visible or pre-render line. Each tile takes 8 dots:
+/// its nametable byte, attribute byte, then the low and high bytes of its pattern. Each fetch
+/// puts its address out on the first of its two dots. Dots 321-336 fetch the first two tiles
+/// of the next line, and 337-340 fetch its first nametable byte twice more, unused.
+fn fetch_background(s: &mut State) {
+    let tick = s.ppu.tick;
+    let v = s.ppu.v;
+    if tick & 0x7 == 1 && ((9..=257).contains(&tick) || (329..=337).contains(&tick)) {
+        reload_background(&mut s.ppu);
+    }
+    match tick {
+        1..=256 | 321..=336 => match (tick - 1) % 8 {
+            0 => {
+                s.ppu.bg_next_tile = s.ppu_peek(0x2000 | (v & 0x0FFF));
+            }
+            2 => {
+                let at_addr = 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+                let at_data = s.ppu_peek(at_addr);
+                // process attribute data to select correct tile
+                s.ppu.bg_next_attribute = (at_data >> (((v >> 4) & 4) | (v & 2))) & 3;
+            }
+            4 => {
+                let pattern_addr = background_pattern_addr(&s.ppu);
+                s.ppu.bg_next_lo = s.ppu_peek(pattern_addr);
+            }
+            6 => {
+                let pattern_addr = background_pattern_addr(&s.ppu);
+                s.ppu.bg_next_hi = s.ppu_peek(pattern_addr | 0x8);
+            }
+            7 => increment_scroll_x(&mut s.ppu),
+            _ => {}
+        },
+        337 | 339 => {
+            s.ppu_peek(0x2000 | (v & 0x0FFF));
+        }
+        _ => {}
+    }
+}
+
+fn background_pattern_addr(ppu: &PpuState) -> u16 {
+    0 | ((ppu.v >> 12) & 0x7)
+        | (ppu.bg_next_tile as u16) << 4
+        | (ppu.flag_background_table_addr as u16) << 12
+}
+
+/// Moves the fetched tile into the low bytes of the shift registers.
+fn reload_background(ppu: &mut PpuState) {
+    ppu.bg_shift_lo = (ppu.bg_shift_lo & 0xFF00) | ppu.bg_next_lo as u16;
+    ppu.bg_shift_hi = (ppu.bg_shift_hi & 0xFF00) | ppu.bg_next_hi as u16;
+    let fill = |bit: u8| if bit > 0 { 0xFF } else { 0x00 };
+    ppu.bg_shift_attribute_lo =
+        (ppu.bg_shift_attribute_lo & 0xFF00) | fill(ppu.bg_next_attribute & 0x1);
+    ppu.bg_shift_attribute_hi =
+        (ppu.bg_shift_attribute_hi & 0xFF00) | fill(ppu.bg_next_attribute & 0x2);
+}
+
+fn shift_background(ppu: &mut PpuState) {
+    ppu.bg_shift_lo <<= 1;
+    ppu.bg_shift_hi <<= 1;
+    ppu.bg_shift_attribute_lo <<= 1;
+    ppu.bg_shift_attribute_hi <<= 1;
+}
+
+// Whether the PPU is rendering: on visible and pre-render lines with rendering enabled. Its
+// fetches own v, and sprite evaluation and fetches own OAM.
+fn is_rendering(s: &State) -> bool {
+    let rendering_enabled = s.ppu.flag_render_sprites || s.ppu.flag_render_background;
+    rendering_enabled && (s.ppu.scanline < 240 || s.ppu.scanline == s.region.scanlines() - 1)
+}
+
+// Handles the reset line. Unlike the CPU, the PPU's reset clears most of its registers, but v,
+// OAM, and the palette are left alone.
+// https://wiki.nesdev.com/w/index.php/PPU_power_up_state
+pub fn reset(s: &mut State) {
+    catch_up(s);
+
+    s.ppu.warmup = true;
+    s.ppu.data_buffer = 0;
+    s.ppu.t = 0;
+    s.ppu.x = 0;
+    s.ppu.w = 0;
+    poke_ctrl(&mut s.ppu, 0);
+    poke_mask(&mut s.ppu, 0);
+}
+
+// Drives the bits of the I/O latch in `mask` with `data`.
+fn refresh_latch(ppu: &mut PpuState, data: u8, mask: u8) {
+    ppu.latch = (ppu.latch & !mask) | (data & mask);
+    for bit in 0..8 {
+        if mask & (1 << bit) != 0 {
+            ppu.latch_refreshed[bit] = ppu.cycles;
+        }
+    }
+}
+
+// Each bit of the I/O latch decays to 0 if it hasn't been driven in a while.
+// https://wiki.nesdev.com/w/index.php/Open_bus_behavior#PPU_open_bus
+fn decay_latch(ppu: &mut PpuState) {
+    for bit in 0..8 {
+        if ppu.cycles - ppu.latch_refreshed[bit] > LATCH_DECAY_DOTS {
+            ppu.latch &= !(1 << bit);
+        }
     }
 }
 
 pub fn peek_register(s: &mut State, register: u16) -> u8 {
     catch_up(s);
+    decay_latch(&mut s.ppu);
 
-    s.ppu.latch = match register {
+    // Which bits of the latch this register drives; the rest are open bus.
+    let (data, mask) = match register {
         2 => {
             // PPUSTATUS
-            let data = (s.ppu.latch & 0x1F)
-                | (s.ppu.sprite_overflow) << 5
+            let data = (s.ppu.sprite_overflow) << 5
                 | (s.ppu.sprite0_hit as u8) << 6
                 | (s.ppu.vblank) << 7;
 
             s.ppu.vblank = 0;
             s.ppu.w = 0;
-            data
+            (data, 0xE0)
         }
         4 => {
             // OAMDATA
-            // TODO: handle returning (0xFF [or others]) during rendering
-            s.ppu.oam_1[s.ppu.oam_addr]
+            // During rendering, this is whatever sprite evaluation has on the OAM bus.
+            let data = if is_rendering(s) {
+                s.ppu.sprite_eval_read
+            } else {
+                s.ppu.oam_1[s.ppu.oam_addr]
+            };
+            (data, 0xFF)
         }
         7 => {
             // PPUDATA
             let mut data = s.ppu_peek(s.ppu.v);
-            if s.ppu.v <= 0x3EFF {
+            let mask = if s.ppu.v & 0x3FFF <= 0x3EFF {
                 // buffer this read
                 std::mem::swap(&mut data, &mut s.ppu.data_buffer);
+                0xFF
             } else {
                 s.ppu.data_buffer = s.ppu_peek(s.ppu.v - 0x1000);
-            }
-
-            s.ppu.v += if s.ppu.flag_vram_increment == 0 {
-                1
-            } else {
-                32
+                // Palette RAM is 6 bits wide.
+                0x3F
             };
-            data
+
+            increment_vram_addr(s);
+            (data, mask)
         }
-        _ => s.ppu.latch,
+        _ => (0, 0),
     };
+    refresh_latch(&mut s.ppu, data, mask);
     s.ppu.latch
 }
 
 pub fn poke_register(s: &mut State, register: u16, data: u8) {
-    catch_up(s);
+    if register < 8 {
+        catch_up_to_write(s);
+    } else {
+        catch_up(s);
+    }
 
-    s.ppu.latch = data;
+    if register < 8 {
+        refresh_latch(&mut s.ppu, data, 0xFF);
+    }
+    if s.ppu.warmup && matches!(register, 0 | 1 | 5 | 6) {
+        return;
+    }
     match register {
         0 => {
             // PPUCTRL
             // t: ...BA.. ........ = d: ......BA
             s.ppu.t = (s.ppu.t & 0b1111_0011_1111_1111) | (((data & 0b11) as u16) << 10);
-
-            s.ppu.flag_vram_increment = (data >> 2) & 0x1;
-            s.ppu.flag_sprite_table_addr = (data >> 3) & 0x1;
-            s.ppu.flag_background_table_addr = (data >> 4) & 0x1;
-            s.ppu.flag_sprite_size = (data >> 5) & 0x1;
-            s.ppu.flag_master_slave = (data >> 6) & 0x1;
-            s.ppu.flag_generate_nmi = (data >> 7) & 0x1 > 0;
+            poke_ctrl(&mut s.ppu, data);
         }
         1 => {
             // PPUMASK
-            s.ppu.flag_grayscale = (data >> 0) & 0x1 > 0;
-            s.ppu.flag_show_background_left = (data >> 1) & 0x1 > 0;
-            s.ppu.flag_show_sprites_left = (data >> 2) & 0x1 > 0;
-            s.ppu.flag_render_background = (data >> 3) & 0x1 > 0;
-            s.ppu.flag_render_sprites = (data >> 4) & 0x1 > 0;
-            s.ppu.flag_emphasize_red = (data >> 5) & 0x1 > 0;
-            s.ppu.flag_emphasize_green = (data >> 6) & 0x1 > 0;
-            s.ppu.flag_emphasize_blue = (data >> 7) & 0x1 > 0;
+            poke_mask(&mut s.ppu, data);
         }
         3 => {
             // OAMADDR
@@ -543,7 +863,10 @@ pub fn poke_register(s: &mut State, register: u16, data: u8) {
         }
         4 => {
             // OAMDATA
-            if !s.ppu.is_rendering {
+            if is_rendering(s) {
+                // The write is lost, and OAMADDR is bumped to the next sprite.
+                s.ppu.oam_addr = (s.ppu.oam_addr + 4) & 0xFF;
+            } else {
                 s.ppu.oam_1[s.ppu.oam_addr] = data;
                 s.ppu.oam_addr = (s.ppu.oam_addr + 1) & 0xFF;
             }
@@ -579,11 +902,7 @@ pub fn poke_register(s: &mut State, register: u16, data: u8) {
         7 => {
             // PPUDATA
             s.ppu_poke(s.ppu.v, data);
-            s.ppu.v += if s.ppu.flag_vram_increment == 0 {
-                1
-            } else {
-                32
-            };
+            increment_vram_addr(s);
         }
         0x4014 => {
             // OAMDMA
@@ -599,3 +918,348 @@ pub fn poke_register(s: &mut State, register: u16, data: u8) {
         _ => {}
     };
 }
+
+// After a $2007 access, v moves on to the next address. While rendering, the PPU is using v
+// itself, and the access instead bumps both coarse X and Y like its own fetches do.
+// https://wiki.nesdev.com/w/index.php/PPU_scrolling#.242007_reads_and_writes
+fn increment_vram_addr(s: &mut State) {
+    if is_rendering(s) {
+        increment_scroll_x(&mut s.ppu);
+        increment_scroll_y(&mut s.ppu);
+    } else {
+        s.ppu.v += if s.ppu.flag_vram_increment == 0 {
+            1
+        } else {
+            32
+        };
+        s.ppu.v &= 0x7FFF;
+    }
+}
+
+fn poke_ctrl(ppu: &mut PpuState, data: u8) {
+    ppu.flag_vram_increment = (data >> 2) & 0x1;
+    ppu.flag_sprite_table_addr = (data >> 3) & 0x1;
+    ppu.flag_background_table_addr = (data >> 4) & 0x1;
+    ppu.flag_sprite_size = (data >> 5) & 0x1;
+    ppu.flag_master_slave = (data >> 6) & 0x1;
+    ppu.flag_generate_nmi = (data >> 7) & 0x1 > 0;
+}
+
+fn poke_mask(ppu: &mut PpuState, data: u8) {
+    ppu.flag_grayscale = (data >> 0) & 0x1 > 0;
+    ppu.flag_show_background_left = (data >> 1) & 0x1 > 0;
+    ppu.flag_show_sprites_left = (data >> 2) & 0x1 > 0;
+    ppu.flag_render_background = (data >> 3) & 0x1 > 0;
+    ppu.flag_render_sprites = (data >> 4) & 0x1 > 0;
+    ppu.flag_emphasize_red = (data >> 5) & 0x1 > 0;
+    ppu.flag_emphasize_green = (data >> 6) & 0x1 > 0;
+    ppu.flag_emphasize_blue = (data >> 7) & 0x1 > 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapper::Mapper;
+    use crate::power::MemoryFiller;
+    use crate::{Cartridge, Debug, PowerOnPattern};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Runs a test on a thread with room for the emulator's state, which debug builds copy
+    /// around on the stack.
+    fn run(test: fn()) {
+        std::thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(test)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    /// An NROM game whose tile 0 has every other column in color 1, with the PPU past its
+    /// warmup at the top of the frame.
+    fn new_state() -> State {
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.extend(vec![0xEA; 0x4000]);
+        let mut chr = vec![0; 0x2000];
+        chr[..8].copy_from_slice(&[0xAA; 8]);
+        data.extend(chr);
+        let cart = Cartridge::load(&data);
+        let mut s = State::new(Debug::default(), cart, PowerOnPattern::Zero, Region::Ntsc);
+        s.ppu.warmup = false;
+        s
+    }
+
+    /// Runs the PPU until it's about to do `dot` of `scanline`.
+    fn run_until(s: &mut State, scanline: u16, dot: u16) {
+        while (s.ppu.scanline, s.ppu.tick) != (scanline, dot) {
+            emulate(s, 1);
+        }
+    }
+
+    /// Puts sprites 0-7 on scanline 10 and the rest below the screen, then `extra` over them,
+    /// and turns on sprite rendering.
+    fn place_sprites(s: &mut State, extra: &[(usize, [u8; 4])]) {
+        for i in 0..64 {
+            let sprite = if i < 8 {
+                [10, 0x40 + i as u8, 0, 0x80 + i as u8]
+            } else {
+                [0xFF; 4]
+            };
+            s.ppu.oam_1[i * 4..i * 4 + 4].copy_from_slice(&sprite);
+        }
+        for &(i, sprite) in extra {
+            s.ppu.oam_1[i * 4..i * 4 + 4].copy_from_slice(&sprite);
+        }
+        s.ppu.flag_render_sprites = true;
+    }
+
+    #[test]
+    fn ninth_sprite_sets_overflow() {
+        run(|| {
+            let mut s = new_state();
+            place_sprites(&mut s, &[(8, [10, 0x48, 0, 0x88])]);
+            run_until(&mut s, 10, 65);
+            // Each sprite in range takes 8 dots to copy, so the ninth is found on dot 130.
+            for dot in 65..=256 {
+                emulate(&mut s, 1);
+                assert_eq!(s.ppu.sprite_overflow, (dot >= 130) as u8, "dot {}", dot);
+                match dot {
+                    // Its tile number is read from primary OAM...
+                    131 => assert_eq!(peek_register(&mut s, 4), 0x48),
+                    // ...and the write that would copy it reads secondary OAM instead.
+                    132 => assert_eq!(peek_register(&mut s, 4), 10),
+                    _ => {}
+                }
+            }
+            assert_eq!(s.ppu.oam_2[..], s.ppu.oam_1[..32]);
+        });
+    }
+
+    #[test]
+    fn overflow_check_scans_diagonally() {
+        run(|| {
+            // Only eight sprites are on the line, but sprite 9's tile number is checked as its
+            // Y coordinate, and is in range.
+            let mut s = new_state();
+            place_sprites(&mut s, &[(9, [0xFF, 10, 0xFF, 0xFF])]);
+            run_until(&mut s, 10, 129);
+            emulate(&mut s, 2);
+            assert_eq!((s.ppu.oam_addr, s.ppu.sprite_overflow), (37, 0));
+            emulate(&mut s, 2);
+            assert_eq!((s.ppu.oam_addr, s.ppu.sprite_overflow), (38, 1));
+            run_until(&mut s, 10, 257);
+            assert_eq!(s.ppu.sprite_overflow, 1);
+
+            // Sprite 9 really is on the line, but its tile number, sprite 10's attributes and
+            // sprite 11's X coordinate are checked instead of their Y coordinates.
+            let mut s = new_state();
+            place_sprites(&mut s, &[(9, [10, 0x49, 0, 0x89])]);
+            run_until(&mut s, 10, 129);
+            for &addr in [37, 42, 47, 48].iter() {
+                emulate(&mut s, 2);
+                assert_eq!(s.ppu.oam_addr, addr);
+            }
+            run_until(&mut s, 10, 257);
+            assert_eq!(s.ppu.sprite_overflow, 0);
+            assert_eq!(s.ppu.oam_2[..], s.ppu.oam_1[..32]);
+        });
+    }
+
+    #[test]
+    fn oam_addr_corruption() {
+        run(|| {
+            // Writes to OAMDATA while rendering are dropped, and bump OAMADDR a whole sprite.
+            let mut s = new_state();
+            place_sprites(&mut s, &[]);
+            run_until(&mut s, 10, 300);
+            poke_register(&mut s, 4, 0x55);
+            assert_eq!(s.ppu.oam_addr, 4);
+            assert!(!s.ppu.oam_1.contains(&0x55));
+
+            // Rendering starting with OAMADDR at 8 or more copies 8 bytes over sprites 0-1.
+            let mut s = new_state();
+            for (i, byte) in s.ppu.oam_1.iter_mut().enumerate() {
+                *byte = i as u8;
+            }
+            run_until(&mut s, 261, 0);
+            s.ppu.oam_addr = 0x13;
+            s.ppu.flag_render_sprites = true;
+            emulate(&mut s, 9);
+            let expected: Vec<u8> = (0x10..0x18).chain(8..0x100).map(|i| i as u8).collect();
+            assert_eq!(s.ppu.oam_1[..], expected[..]);
+        });
+    }
+
+    #[test]
+    fn oam_data_reads_while_rendering() {
+        run(|| {
+            let mut s = new_state();
+            place_sprites(&mut s, &[]);
+            run_until(&mut s, 10, 1);
+            // Secondary OAM is cleared, and reads see $FF.
+            for _ in 1..=64 {
+                emulate(&mut s, 1);
+                assert_eq!(peek_register(&mut s, 4), 0xFF);
+            }
+            // The sprite fetches read each sprite's Y, tile, attributes, then X five times.
+            run_until(&mut s, 10, 257);
+            for n in 0..8 {
+                for k in 0..8 {
+                    emulate(&mut s, 1);
+                    let expected = s.ppu.oam_1[n * 4 + k.min(3)];
+                    assert_eq!(peek_register(&mut s, 4), expected, "sprite {} dot {}", n, k);
+                }
+            }
+        });
+    }
+
+    /// The colors of a scanline's pixels.
+    fn line(s: &State, scanline: usize) -> &[u16] {
+        &s.ppu.index_buffer[scanline * FRAME_WIDTH..(scanline + 1) * FRAME_WIDTH]
+    }
+
+    #[test]
+    fn backdrop_shows_palette_entry_at_v() {
+        run(|| {
+            let mut s = new_state();
+            s.ppu.palette[0] = 0x0F;
+            s.ppu.palette[5] = 0x21;
+            s.ppu.v = 0x2000;
+            run_until(&mut s, 1, 0);
+            assert!(line(&s, 0).iter().all(|&pixel| pixel == 0x0F));
+            s.ppu.v = 0x3F05;
+            run_until(&mut s, 2, 0);
+            assert!(line(&s, 1).iter().all(|&pixel| pixel == 0x21));
+        });
+    }
+
+    #[test]
+    fn mid_line_writes_land_on_their_dot() {
+        run(|| {
+            // A write reaches the PPU 7/12 of the way through the CPU cycle, 1.75 dots in, so
+            // the first dot of the cycle still sees the old value.
+            let mut s = new_state();
+            s.ppu.palette[0] = 0x16;
+            s.cpu.cycles = 200;
+            catch_up(&mut s);
+            assert_eq!((s.ppu.scanline, s.ppu.tick), (1, 238));
+            poke_register(&mut s, 1, 0x01); // grayscale
+            assert_eq!((s.ppu.scanline, s.ppu.tick), (1, 239));
+            run_until(&mut s, 2, 0);
+            for (x, &pixel) in line(&s, 1).iter().enumerate() {
+                assert_eq!(pixel, if x < 238 { 0x16 } else { 0x10 }, "x {}", x);
+            }
+
+            // Tile 0 alternates colors 1 and 0, so scrolling it one pixel swaps them.
+            let mut s = new_state();
+            s.ppu.palette[0] = 0x0F;
+            s.ppu.palette[1] = 0x30;
+            s.ppu.flag_render_background = true;
+            s.cpu.cycles = 200;
+            catch_up(&mut s);
+            poke_register(&mut s, 5, 0x01);
+            assert_eq!(s.ppu.x, 1);
+            run_until(&mut s, 2, 0);
+            for (x, &pixel) in line(&s, 1).iter().enumerate().skip(8) {
+                let color_1 = (x % 2 == 0) == (x < 238);
+                assert_eq!(pixel, if color_1 { 0x30 } else { 0x0F }, "x {}", x);
+            }
+        });
+    }
+
+    /// A mapper that logs the addresses the PPU reads, and returns 0 for all of them.
+    #[derive(Serialize)]
+    struct Recorder {
+        #[serde(skip)]
+        log: Rc<RefCell<Vec<u16>>>,
+    }
+
+    impl Mapper for Recorder {
+        fn peek(&mut self, addr: u16) -> Option<u8> {
+            self.log.borrow_mut().push(addr);
+            Some(0)
+        }
+        fn poke(&mut self, _addr: u16, _val: u8) {}
+        fn peek_chr(&self, _addr: u16) -> u8 {
+            0
+        }
+        fn get_id(&self) -> u8 {
+            0xFF
+        }
+        fn update_cartridge(&mut self, _cartridge: Cartridge) {}
+        fn power_on(&mut self, _filler: &mut MemoryFiller) {}
+    }
+
+    /// What a PPU fetch is for, with the background at $0000 and sprites at $1000.
+    fn fetch_kind(addr: u16) -> &'static str {
+        match addr {
+            0x2000..=0x3EFF if addr & 0x3FF >= 0x3C0 => "attribute",
+            0x2000..=0x3EFF => "nametable",
+            _ => match (addr & 0x1000 != 0, addr & 0x8 != 0) {
+                (false, false) => "background low",
+                (false, true) => "background high",
+                (true, false) => "sprite low",
+                (true, true) => "sprite high",
+            },
+        }
+    }
+
+    #[test]
+    fn scanline_fetch_order() {
+        run(|| {
+            let mut s = new_state();
+            let log = Rc::new(RefCell::new(Vec::new()));
+            s.mapper = Box::new(Recorder { log: log.clone() });
+            s.ppu.flag_render_background = true;
+            s.ppu.flag_render_sprites = true;
+            s.ppu.flag_sprite_table_addr = 1;
+            run_until(&mut s, 1, 0);
+            log.borrow_mut().clear();
+            let mut fetches: Vec<(usize, u16)> = vec![];
+            for dot in 0..=340 {
+                emulate(&mut s, 1);
+                fetches.extend(log.borrow_mut().drain(..).map(|addr| (dot, addr)));
+            }
+
+            // Each fetch takes two dots, and puts its address out on the first.
+            let background = [
+                "nametable",
+                "attribute",
+                "background low",
+                "background high",
+            ];
+            let sprites = ["nametable", "nametable", "sprite low", "sprite high"];
+            let mut expected = vec![];
+            for dot in (1..=340).step_by(2) {
+                let kind = match dot {
+                    1..=256 | 321..=336 => background[(dot - 1) % 8 / 2],
+                    257..=320 => sprites[(dot - 257) % 8 / 2],
+                    _ => "nametable",
+                };
+                expected.push((dot, kind));
+            }
+            let kinds: Vec<_> = fetches
+                .iter()
+                .map(|&(dot, addr)| (dot, fetch_kind(addr)))
+                .collect();
+            assert_eq!(kinds, expected);
+
+            // The two at the end fetch the nametable byte of the next line's third tile, unused.
+            let addr_at = |dot| fetches.iter().find(|&&(d, _)| d == dot).unwrap().1;
+            assert_eq!(addr_at(337), addr_at(339));
+            assert_eq!(addr_at(337), 0x2000 | (s.ppu.v & 0xFFF));
+
+            // MMC3 counts scanlines by A12 rising, once per sprite.
+            let mut a12 = false;
+            let mut rises = vec![];
+            for &(dot, addr) in fetches.iter() {
+                if !a12 && addr & 0x1000 != 0 {
+                    rises.push(dot);
+                }
+                a12 = addr & 0x1000 != 0;
+            }
+            assert_eq!(rises, (261..=317).step_by(8).collect::<Vec<_>>());
+        });
+    }
+}
@@ -0,0 +1,150 @@
+use super::nes::State;
+use std::str::FromStr;
+
+/// Where PRG-RAM starts in the searched memory, after the 2 KB of CPU RAM.
+const PRG_RAM_INDEX: usize = 0x800;
+
+/// How a candidate's current value must compare to its value at the last search step.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SearchFilter {
+    Equal,
+    Changed,
+    Greater,
+    Less,
+    /// The current value is exactly this.
+    Value(u8),
+}
+
+impl SearchFilter {
+    fn matches(self, previous: u8, current: u8) -> bool {
+        match self {
+            SearchFilter::Equal => current == previous,
+            SearchFilter::Changed => current != previous,
+            SearchFilter::Greater => current > previous,
+            SearchFilter::Less => current < previous,
+            SearchFilter::Value(value) => current == value,
+        }
+    }
+}
+
+impl FromStr for SearchFilter {
+    type Err = String;
+
+    /// One of "eq", "ne", "gt", "lt", or a value (decimal, or hex with a '$' or "0x" prefix).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "eq" => Ok(SearchFilter::Equal),
+            "ne" => Ok(SearchFilter::Changed),
+            "gt" => Ok(SearchFilter::Greater),
+            "lt" => Ok(SearchFilter::Less),
+            _ => parse_number(s)
+                .and_then(|v| if v <= 0xFF { Some(v as u8) } else { None })
+                .map(SearchFilter::Value)
+                .ok_or_else(|| format!("unknown search filter: {}", s)),
+        }
+    }
+}
+
+/// Parses a decimal number, or a hex number prefixed with '$' or "0x".
+pub fn parse_number(s: &str) -> Option<u16> {
+    if let Some(hex) = s.strip_prefix('$').or_else(|| s.strip_prefix("0x")) {
+        u16::from_str_radix(hex, 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+/// Narrows down where a game keeps a value by comparing snapshots of CPU RAM and PRG-RAM
+/// between frames, and freezes addresses to fixed values.
+#[derive(Default)]
+pub struct RamSearch {
+    /// Memory at the last search step.
+    snapshot: Vec<u8>,
+    /// Indices into `snapshot` still in the running.
+    candidates: Vec<usize>,
+    /// Addresses written back every frame.
+    frozen: Vec<(u16, u8)>,
+}
+
+impl RamSearch {
+    /// Starts over with every address as a candidate.
+    pub fn reset(&mut self, s: &mut State) {
+        self.snapshot = snapshot(s);
+        self.candidates = (0..self.snapshot.len()).collect();
+    }
+
+    /// Keeps the candidates that pass `filter`, then takes a new snapshot to compare against.
+    pub fn filter(&mut self, s: &mut State, filter: SearchFilter) {
+        let current = snapshot(s);
+        if current.len() != self.snapshot.len() {
+            self.reset(s);
+            return;
+        }
+        let previous = &self.snapshot;
+        self.candidates
+            .retain(|&i| filter.matches(previous[i], current[i]));
+        self.snapshot = current;
+    }
+
+    /// The remaining candidates, as (CPU address, value at the last search step).
+    pub fn candidates(&self) -> Vec<(u16, u8)> {
+        self.candidates
+            .iter()
+            .map(|&i| (index_to_addr(i), self.snapshot[i]))
+            .collect()
+    }
+
+    /// Holds `addr` at `value`. `addr` must be in CPU RAM ($0000-$07FF) or PRG-RAM
+    /// ($6000-$7FFF); returns false otherwise.
+    pub fn freeze(&mut self, s: &mut State, addr: u16, value: u8) -> bool {
+        if addr_to_index(s, addr).is_none() {
+            return false;
+        }
+        self.unfreeze(addr);
+        self.frozen.push((addr, value));
+        true
+    }
+
+    pub fn unfreeze(&mut self, addr: u16) {
+        self.frozen.retain(|&(a, _)| a != addr);
+    }
+
+    pub fn frozen(&self) -> &[(u16, u8)] {
+        &self.frozen
+    }
+
+    /// Writes the frozen values back. Called once per frame.
+    pub fn apply_freezes(&self, s: &mut State) {
+        for &(addr, value) in self.frozen.iter() {
+            match addr_to_index(s, addr) {
+                Some(i) if i < PRG_RAM_INDEX => s.ram[i] = value,
+                Some(i) => s.mapper.prg_ram()[i - PRG_RAM_INDEX] = value,
+                None => {}
+            }
+        }
+    }
+}
+
+fn snapshot(s: &mut State) -> Vec<u8> {
+    let mut memory = s.ram.to_vec();
+    memory.extend_from_slice(s.mapper.prg_ram());
+    memory
+}
+
+fn index_to_addr(i: usize) -> u16 {
+    if i < PRG_RAM_INDEX {
+        i as u16
+    } else {
+        (0x6000 + i - PRG_RAM_INDEX) as u16
+    }
+}
+
+fn addr_to_index(s: &mut State, addr: u16) -> Option<usize> {
+    match addr {
+        0x0000..=0x07FF => Some(addr as usize),
+        0x6000..=0x7FFF if ((addr - 0x6000) as usize) < s.mapper.prg_ram().len() => {
+            Some(PRG_RAM_INDEX + (addr - 0x6000) as usize)
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,78 @@
+use super::nes::AUDIO_SAMPLE_RATE;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// The console's TV system, which determines the CPU, PPU, and APU timing.
+/// https://wiki.nesdev.com/w/index.php/Cycle_reference_chart
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    /// The most common Famiclone: a PAL-like frame, but with NTSC-like CPU/PPU ratio and APU.
+    Dendy,
+}
+
+impl Region {
+    /// PPU dots per CPU cycle, as a (numerator, denominator) fraction.
+    pub(crate) fn ppu_clock_ratio(&self) -> (u64, u64) {
+        match self {
+            Region::Ntsc | Region::Dendy => (3, 1),
+            Region::Pal => (16, 5),
+        }
+    }
+
+    /// Scanlines per frame, including the pre-render line.
+    pub(crate) fn scanlines(&self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    /// The scanline on which the vblank flag is set (and NMI fires).
+    pub(crate) fn vblank_scanline(&self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    /// Whether the pre-render line is a dot shorter on odd frames with rendering enabled.
+    pub(crate) fn skips_odd_frame_dot(&self) -> bool {
+        *self == Region::Ntsc
+    }
+
+    /// Nominal frames per second, used to size the audio buffer and pace the frontends.
+    pub fn frame_rate(&self) -> usize {
+        match self {
+            Region::Ntsc => 60,
+            Region::Pal | Region::Dendy => 50,
+        }
+    }
+
+    pub(crate) fn audio_samples_per_frame(&self) -> usize {
+        AUDIO_SAMPLE_RATE / self.frame_rate()
+    }
+
+    /// CPU cycles between APU frame counter steps.
+    pub(crate) fn apu_frame_interval(&self) -> u64 {
+        match self {
+            Region::Ntsc | Region::Dendy => 7457,
+            Region::Pal => 8313,
+        }
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ntsc" => Ok(Region::Ntsc),
+            "pal" => Ok(Region::Pal),
+            "dendy" => Ok(Region::Dendy),
+            _ => Err(format!("unknown region: {}", s)),
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use super::input::{DeviceKind, InputDevice};
+use super::nes::{FRAME_HEIGHT, FRAME_WIDTH};
+use super::palette;
+use super::ppu::PpuState;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+
+/// How far around the aim point the photodiode can see, in pixels.
+const SENSE_RADIUS: i32 = 2;
+/// How many scanlines the photodiode keeps reporting light after the beam passes.
+const SENSE_SCANLINES: i32 = 20;
+/// Minimum brightness (0-1) for a pixel to count as light.
+const SENSE_THRESHOLD: f32 = 0.75;
+
+/// The Zapper light gun.
+/// https://wiki.nesdev.com/w/index.php/Zapper
+#[derive(Serialize, Deserialize)]
+pub struct Zapper {
+    /// Where the gun is aimed, in screen pixels. May be off-screen.
+    x: i32,
+    y: i32,
+    trigger: bool,
+}
+
+impl Zapper {
+    pub fn new() -> Zapper {
+        Zapper {
+            x: -1,
+            y: -1,
+            trigger: false,
+        }
+    }
+
+    pub fn set_state(&mut self, x: i32, y: i32, trigger: bool) {
+        self.x = x;
+        self.y = y;
+        self.trigger = trigger;
+    }
+
+    /// Whether the photodiode sees light: the beam has recently drawn something bright near the
+    /// aim point.
+    fn detects_light(&self, ppu: &PpuState) -> bool {
+        let scanline = ppu.scanline as i32;
+        let dot = ppu.tick as i32 - 1;
+        for y in (self.y - SENSE_RADIUS)..=(self.y + SENSE_RADIUS) {
+            if y < 0 || y >= FRAME_HEIGHT as i32 || scanline - y > SENSE_SCANLINES {
+                continue;
+            }
+            for x in (self.x - SENSE_RADIUS)..=(self.x + SENSE_RADIUS) {
+                if x < 0 || x >= FRAME_WIDTH as i32 {
+                    continue;
+                }
+                // Pixels the beam hasn't reached yet this frame are dark.
+                let drawn = y < scanline || (y == scanline && x < dot);
+                if !drawn {
+                    continue;
+                }
+                // The frame buffer isn't converted to RGB until the frame is done.
+                let pixel = ppu.index_buffer[(y as usize * FRAME_WIDTH) + x as usize];
+                if palette::luma(pixel) >= SENSE_THRESHOLD {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+impl InputDevice for Zapper {
+    fn read(&mut self, _addr: u16, ppu: &PpuState) -> u8 {
+        // D3: 0 if light is detected. D4: 1 if the trigger is pulled.
+        let light = (!self.detects_light(ppu) as u8) << 3;
+        let trigger = (self.trigger as u8) << 4;
+        light | trigger
+    }
+
+    fn write(&mut self, _data: u8) {}
+
+    fn get_kind(&self) -> DeviceKind {
+        DeviceKind::Zapper
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
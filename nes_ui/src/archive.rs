@@ -0,0 +1,107 @@
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use flate2::read::GzDecoder;
+
+/// A rom read from disk, possibly out of a .zip or .gz archive.
+pub struct RomFile {
+    /// The rom's own file name, which for an archive is the name inside it.
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// Only iNES roms can be loaded; .fds and .nsf files in the same archive are skipped.
+fn is_rom(name: &str) -> bool {
+    extension(Path::new(name)) == "nes"
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase()
+}
+
+/// Reads a rom file. From a .zip, this is `entry` if given, otherwise the first .nes file in it.
+/// A .gz or a plain file holds a single rom, so `entry` is an error there.
+pub fn read_rom(path: &Path, entry: Option<&str>) -> Result<RomFile, String> {
+    if entry.is_some() && extension(path) != "zip" {
+        return Err("--archive-entry only applies to .zip files".to_string());
+    }
+    let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    match extension(path).as_str() {
+        "zip" => read_zip(file, entry),
+        "gz" => {
+            let mut decoder = GzDecoder::new(file);
+            let mut data = vec![];
+            decoder
+                .read_to_end(&mut data)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            // Prefer the original name stored in the header, if any.
+            let name = decoder
+                .header()
+                .and_then(|h| h.filename())
+                .map(|name| String::from_utf8_lossy(name).into_owned())
+                .unwrap_or_else(|| file_name.trim_end_matches(".gz").to_string());
+            Ok(RomFile { name, data })
+        }
+        _ => {
+            let mut data = vec![];
+            (&file)
+                .read_to_end(&mut data)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            Ok(RomFile {
+                name: file_name,
+                data,
+            })
+        }
+    }
+}
+
+fn read_zip(file: File, entry: Option<&str>) -> Result<RomFile, String> {
+    let mut zip = zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
+    // In archive order, so "first" means something.
+    let names = (0..zip.len())
+        .map(|i| Ok(zip.by_index(i)?.name().to_string()))
+        .collect::<zip::result::ZipResult<Vec<String>>>()
+        .map_err(|e| e.to_string())?;
+    let roms: Vec<&String> = names.iter().filter(|name| is_rom(name)).collect();
+    let name = match entry {
+        Some(entry) => names
+            .iter()
+            .find(|name| name.as_str() == entry)
+            .ok_or_else(|| format!("no {} in the archive", entry))
+            .and_then(|name| {
+                if is_rom(name) {
+                    Ok(name)
+                } else {
+                    Err(format!("{} is not a .nes rom", name))
+                }
+            })?,
+        None => {
+            if roms.len() > 1 {
+                println!("[main] Archive has several roms; pick one with --archive-entry:");
+                for name in roms.iter() {
+                    println!("[main]   {}", name);
+                }
+            }
+            roms.first().ok_or("no .nes rom in the archive")?
+        }
+    };
+
+    let mut data = vec![];
+    zip.by_name(name)
+        .and_then(|mut f| Ok(f.read_to_end(&mut data)?))
+        .map_err(|e| e.to_string())?;
+    // Entries may be in folders; only the file name matters.
+    let name = PathBuf::from(name)
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .into_owned();
+    Ok(RomFile { name, data })
+}
@@ -1,10 +1,14 @@
 extern crate clap;
 extern crate sdl2;
 
+mod archive;
+mod ram_search;
+mod video;
+
 use std::{
     fs::File,
     io::{BufWriter, Read, Write},
-    path::Path,
+    path::{Path, PathBuf},
     time::{Duration, Instant},
 };
 
@@ -12,63 +16,254 @@ use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
 use nes_core::ControllerState;
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::Color;
+use sdl2::rect::Rect;
 use sdl2::render::BlendMode;
 
 const WIDTH: u32 = 256;
 const HEIGHT: u32 = 240;
 const SCALE: u32 = 2;
 
-fn get_controller_state(event_pump: &sdl2::EventPump) -> (ControllerState, ControllerState) {
-    let mut controller1 = ControllerState::default();
-    let controller2 = ControllerState::default();
+/// With a Power Pad attached, its grid takes over controller 2's keys.
+fn get_controller_state(event_pump: &sdl2::EventPump, power_pad: bool) -> [ControllerState; 4] {
+    let mut controllers = [ControllerState::default(); 4];
     let keyboard_state = event_pump.keyboard_state();
     let keys = keyboard_state
         .pressed_scancodes()
         .filter_map(Keycode::from_scancode);
     for key in keys {
-        match key {
-            Keycode::Z => {
-                controller1.a = true;
-            }
-            Keycode::X => {
-                controller1.b = true;
-            }
-            Keycode::RShift => {
-                controller1.select = true;
-            }
-            Keycode::Return => {
-                controller1.start = true;
-            }
-            Keycode::Up => {
-                controller1.up = true;
-            }
-            Keycode::Down => {
-                controller1.down = true;
-            }
-            Keycode::Left => {
-                controller1.left = true;
-            }
-            Keycode::Right => {
-                controller1.right = true;
+        let (player, button): (usize, fn(&mut ControllerState)) = match key {
+            // Controller 1
+            Keycode::Z => (0, |c: &mut ControllerState| c.a = true),
+            Keycode::X => (0, |c: &mut ControllerState| c.b = true),
+            Keycode::RShift => (0, |c: &mut ControllerState| c.select = true),
+            Keycode::Return => (0, |c: &mut ControllerState| c.start = true),
+            Keycode::Up => (0, |c: &mut ControllerState| c.up = true),
+            Keycode::Down => (0, |c: &mut ControllerState| c.down = true),
+            Keycode::Left => (0, |c: &mut ControllerState| c.left = true),
+            Keycode::Right => (0, |c: &mut ControllerState| c.right = true),
+            // Controller 2
+            Keycode::G => (1, |c: &mut ControllerState| c.a = true),
+            Keycode::F => (1, |c: &mut ControllerState| c.b = true),
+            Keycode::Q => (1, |c: &mut ControllerState| c.select = true),
+            Keycode::E => (1, |c: &mut ControllerState| c.start = true),
+            Keycode::W => (1, |c: &mut ControllerState| c.up = true),
+            Keycode::S => (1, |c: &mut ControllerState| c.down = true),
+            Keycode::A => (1, |c: &mut ControllerState| c.left = true),
+            Keycode::D => (1, |c: &mut ControllerState| c.right = true),
+            // Controller 3 (Four Score)
+            Keycode::O => (2, |c: &mut ControllerState| c.a = true),
+            Keycode::U => (2, |c: &mut ControllerState| c.b = true),
+            Keycode::Num7 => (2, |c: &mut ControllerState| c.select = true),
+            Keycode::Num8 => (2, |c: &mut ControllerState| c.start = true),
+            Keycode::I => (2, |c: &mut ControllerState| c.up = true),
+            Keycode::K => (2, |c: &mut ControllerState| c.down = true),
+            Keycode::J => (2, |c: &mut ControllerState| c.left = true),
+            Keycode::L => (2, |c: &mut ControllerState| c.right = true),
+            // Controller 4 (Four Score)
+            Keycode::Kp9 => (3, |c: &mut ControllerState| c.a = true),
+            Keycode::Kp7 => (3, |c: &mut ControllerState| c.b = true),
+            Keycode::Kp1 => (3, |c: &mut ControllerState| c.select = true),
+            Keycode::Kp3 => (3, |c: &mut ControllerState| c.start = true),
+            Keycode::Kp8 => (3, |c: &mut ControllerState| c.up = true),
+            Keycode::Kp5 => (3, |c: &mut ControllerState| c.down = true),
+            Keycode::Kp4 => (3, |c: &mut ControllerState| c.left = true),
+            Keycode::Kp6 => (3, |c: &mut ControllerState| c.right = true),
+            _ => continue,
+        };
+        if player == 1 && power_pad {
+            continue;
+        }
+        button(&mut controllers[player]);
+    }
+    controllers
+}
+
+/// Aims any Zappers at the mouse. Left click fires; right click fires off-screen, which games
+/// use for reloading.
+fn set_zapper_state(nes: &mut nes_core::Nes, event_pump: &sdl2::EventPump, view: &View) {
+    let mouse = event_pump.mouse_state();
+    let (x, y, trigger) = if mouse.right() {
+        (-1, -1, true)
+    } else {
+        let (x, y) = view.to_frame(mouse.x(), mouse.y());
+        (x, y, mouse.left())
+    };
+    for &port in [nes_core::Port::One, nes_core::Port::Two].iter() {
+        if nes.get_input_device(port) == nes_core::DeviceKind::Zapper {
+            nes.set_zapper_state(port, x, y, trigger);
+        }
+    }
+}
+
+/// Turns any Arkanoid paddles with the mouse's horizontal position. Left click fires.
+fn set_arkanoid_state(nes: &mut nes_core::Nes, event_pump: &sdl2::EventPump, view: &View) {
+    let mouse = event_pump.mouse_state();
+    let (x, _) = view.to_frame(mouse.x(), mouse.y());
+    let position = x as f32 / WIDTH as f32;
+    let ports = [
+        nes_core::Port::One,
+        nes_core::Port::Two,
+        nes_core::Port::Expansion,
+    ];
+    for &port in ports.iter() {
+        match nes.get_input_device(port) {
+            nes_core::DeviceKind::ArkanoidNes | nes_core::DeviceKind::ArkanoidFamicom => {
+                nes.set_arkanoid_state(port, position, mouse.left())
             }
             _ => {}
         }
     }
-    (controller1, controller2)
+}
+
+fn power_pad_ports(nes: &nes_core::Nes) -> Vec<nes_core::Port> {
+    [nes_core::Port::One, nes_core::Port::Two]
+        .iter()
+        .copied()
+        .filter(|&port| nes.get_input_device(port) == nes_core::DeviceKind::PowerPad)
+        .collect()
+}
+
+/// Maps the Power Pad's 3x4 grid of buttons onto the keyboard:
+/// 1 2 3 4 / Q W E R / A S D F. These overlap controller 2's keys, which are ignored while a
+/// Power Pad is attached.
+fn set_power_pad_state(nes: &mut nes_core::Nes, event_pump: &sdl2::EventPump) {
+    let ports = power_pad_ports(nes);
+    if ports.is_empty() {
+        return;
+    }
+    let mut buttons = [false; 12];
+    let keyboard_state = event_pump.keyboard_state();
+    let keys = keyboard_state
+        .pressed_scancodes()
+        .filter_map(Keycode::from_scancode);
+    for key in keys {
+        let button = match key {
+            Keycode::Num1 => 1,
+            Keycode::Num2 => 2,
+            Keycode::Num3 => 3,
+            Keycode::Num4 => 4,
+            Keycode::Q => 5,
+            Keycode::W => 6,
+            Keycode::E => 7,
+            Keycode::R => 8,
+            Keycode::A => 9,
+            Keycode::S => 10,
+            Keycode::D => 11,
+            Keycode::F => 12,
+            _ => continue,
+        };
+        buttons[button - 1] = true;
+    }
+    for port in ports {
+        nes.set_power_pad_state(port, buttons);
+    }
+}
+
+/// Turns every cheat off if any are on, otherwise turns them all on, and saves the list.
+fn toggle_cheats(nes: &mut nes_core::Nes, cheats_path: &str) {
+    let enabled = !nes.get_cheats().iter().any(|c| c.enabled);
+    let codes: Vec<String> = nes.get_cheats().iter().map(|c| c.code.clone()).collect();
+    for code in codes.iter() {
+        nes.set_cheat_enabled(code, enabled);
+    }
+    println!(
+        "Cheats {} ({})",
+        if enabled { "on" } else { "off" },
+        codes.join(", ")
+    );
+    save_cheats(nes, cheats_path);
+}
+
+fn load_battery_ram(nes: &mut nes_core::Nes, battery_path: &str) {
+    if let (Some(ram), Ok(data)) = (nes.battery_ram(), std::fs::read(battery_path)) {
+        if data.len() == ram.len() {
+            ram.copy_from_slice(&data);
+            println!("[main] Loaded battery RAM from {}", battery_path);
+        } else {
+            println!("[main] Ignoring {}: wrong size", battery_path);
+        }
+    }
+}
+
+fn save_battery_ram(nes: &mut nes_core::Nes, battery_path: &str) {
+    if let Some(ram) = nes.battery_ram() {
+        std::fs::write(battery_path, ram).unwrap();
+        println!("[main] Saved battery RAM to {}", battery_path);
+    }
+}
+
+fn save_cheats(nes: &nes_core::Nes, cheats_path: &str) {
+    if nes.get_cheats().is_empty() && !Path::new(cheats_path).exists() {
+        return;
+    }
+    std::fs::write(cheats_path, nes.get_cheats_text()).unwrap();
+}
+
+/// Where the picture is in the window.
+struct View<'a> {
+    settings: &'a nes_video::VideoSettings,
+    window_size: (u32, u32),
+}
+
+impl<'a> View<'a> {
+    /// The NES pixel under a point in the window.
+    fn to_frame(&self, x: i32, y: i32) -> (i32, i32) {
+        let (width, height) = self.window_size;
+        self.settings
+            .to_frame((width as usize, height as usize), x, y)
+    }
+}
+
+/// The first of screenshot_<name>_1.png, screenshot_<name>_2.png, ... that doesn't exist yet.
+fn next_screenshot_path(name: &str) -> PathBuf {
+    (1..)
+        .map(|i| PathBuf::from(format!("screenshot_{}_{}.png", name, i)))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+/// Runs `frames` frames without a window, then saves the picture.
+fn take_screenshot(
+    nes: &mut nes_core::Nes,
+    video: &mut video::Video,
+    frames: usize,
+    path: &Path,
+) -> Result<(), String> {
+    for _ in 0..frames {
+        nes.emulate_frame();
+    }
+    video::save_png(path, &video.render(nes))?;
+    println!("[main] Saved screenshot to {}", path.display());
+    Ok(())
 }
 
 fn run_emulator(
     nes: &mut nes_core::Nes,
     mut audio_out: Option<hound::WavWriter<BufWriter<File>>>,
     save_state_path: &str,
+    cheats_path: &str,
+    save_name: &str,
+    mut video: video::Video,
 ) -> Result<(), String> {
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
     let audio_subsystem = sdl_context.audio()?;
 
+    let title = match nes.title() {
+        Some(title) => format!("NES - {}", title),
+        None => "NES".to_string(),
+    };
+    // Filters that scale the picture up don't make the window any bigger.
+    let (picture_width, picture_height) = video.settings.display_size();
+    let factor = video.settings.filter.factor();
     let window = video_subsystem
-        .window("NES", WIDTH * SCALE, HEIGHT * SCALE)
+        .window(
+            &title,
+            (picture_width / factor) as u32 * SCALE,
+            (picture_height / factor) as u32 * SCALE,
+        )
         .position_centered()
+        .resizable()
         .build()
         .map_err(|e| e.to_string())?;
 
@@ -78,8 +273,13 @@ fn run_emulator(
         .build()
         .map_err(|e| e.to_string())?;
     let texture_creator = canvas.texture_creator();
+    let mut picture = video.render(nes);
     let mut texture = texture_creator
-        .create_texture_streaming(sdl2::pixels::PixelFormatEnum::ABGR8888, WIDTH, HEIGHT)
+        .create_texture_streaming(
+            sdl2::pixels::PixelFormatEnum::ABGR8888,
+            picture.width as u32,
+            picture.height as u32,
+        )
         .map_err(|e| e.to_string())?;
     canvas.set_draw_color(Color::RGB(0, 0, 0));
     canvas.clear();
@@ -97,6 +297,10 @@ fn run_emulator(
     let audio_device = audio_subsystem.open_queue::<f32, _>(None, &audio_spec_desired)?;
     audio_device.resume();
 
+    // The display refreshes at its own rate, so 50 Hz regions skip some vsyncs.
+    let frame_duration = Duration::from_secs(1) / nes.region().frame_rate() as u32;
+    let mut next_frame = Instant::now();
+
     let mut frame_counter = 0;
     let mut frame_timer = Instant::now();
     let mut paused = false;
@@ -148,6 +352,27 @@ fn run_emulator(
                     Keycode::Backquote => {
                         nes.debug_toggle_overlay();
                     }
+                    Keycode::F12 => {
+                        let path = next_screenshot_path(save_name);
+                        match video::save_png(&path, &picture) {
+                            Ok(()) => println!("Saved screenshot to {}", path.display()),
+                            Err(e) => println!("Error saving screenshot: {}", e),
+                        }
+                    }
+                    Keycode::R if keymod == sdl2::keyboard::Mod::LGUIMOD => {
+                        nes.reset();
+                    }
+                    Keycode::P if keymod == sdl2::keyboard::Mod::LGUIMOD => {
+                        nes.power_cycle();
+                    }
+                    Keycode::G if keymod == sdl2::keyboard::Mod::LGUIMOD => {
+                        toggle_cheats(nes, cheats_path);
+                    }
+                    Keycode::F if keymod == sdl2::keyboard::Mod::LGUIMOD => {
+                        // Blocks until the prompt is closed.
+                        ram_search::prompt(nes);
+                        next_frame = Instant::now();
+                    }
                     Keycode::S if keymod == sdl2::keyboard::Mod::LGUIMOD => {
                         // Save
                         let state = nes.get_state();
@@ -175,40 +400,69 @@ fn run_emulator(
             }
         }
 
-        let (controller1, controller2) = get_controller_state(&event_pump);
+        let [controller1, controller2, controller3, controller4] =
+            get_controller_state(&event_pump, !power_pad_ports(nes).is_empty());
         nes.set_controller1_state(controller1);
         nes.set_controller2_state(controller2);
+        nes.set_controller3_state(controller3);
+        nes.set_controller4_state(controller4);
+        let view = View {
+            settings: &video.settings,
+            window_size: canvas.window().size(),
+        };
+        set_zapper_state(nes, &event_pump, &view);
+        set_arkanoid_state(nes, &event_pump, &view);
+        set_power_pad_state(nes, &event_pump);
 
         if !paused || single_step {
-            single_step = false;
-            nes.emulate_frame();
-            frame_counter += 1;
-            let buf = nes.get_frame_buffer();
-            texture
-                .update(None, buf, (WIDTH * 4) as usize)
-                .map_err(|e| e.to_string())?;
-            canvas.copy(&texture, None, None)?;
-
-            // Target maximum of 8 frames of samples in the buffer.
-            let samples_queued = (audio_device.size() as usize) / 4;
-            let samples_max = 8 * nes_core::AUDIO_SAMPLE_RATE / 60;
-            if samples_queued < samples_max {
-                let buffer = nes.get_audio_buffer();
-                let to_add = usize::min(buffer.len(), samples_max - samples_queued);
-                audio_device.queue(&buffer[..to_add]);
-            }
-            if let Some(f) = &mut audio_out {
-                for &sample in nes.get_audio_buffer() {
-                    f.write_sample(sample).unwrap();
+            // Allow a little slack so vsync jitter doesn't drop frames.
+            let now = Instant::now();
+            if single_step || now + Duration::from_millis(2) >= next_frame {
+                single_step = false;
+                next_frame = Instant::max(next_frame, now - frame_duration) + frame_duration;
+
+                nes.emulate_frame();
+                frame_counter += 1;
+                picture = video.render(nes);
+                texture
+                    .update(None, &picture.to_rgba(), picture.width * 4)
+                    .map_err(|e| e.to_string())?;
+
+                // Target maximum of 8 frames of samples in the buffer.
+                let samples_queued = (audio_device.size() as usize) / 4;
+                let samples_max = 8 * nes.get_audio_buffer().len();
+                if samples_queued < samples_max {
+                    let buffer = nes.get_audio_buffer();
+                    let to_add = usize::min(buffer.len(), samples_max - samples_queued);
+                    audio_device.queue(&buffer[..to_add]);
+                }
+                if let Some(f) = &mut audio_out {
+                    for &sample in nes.get_audio_buffer() {
+                        f.write_sample(sample).unwrap();
+                    }
                 }
             }
+            let (width, height) = canvas.output_size()?;
+            let (x, y, w, h) = video.settings.fit(width as usize, height as usize);
+            let target = Rect::new(x, y, w, h);
+            canvas.clear();
+            canvas.copy(&texture, None, target)?;
 
             if nes.debug_render_enabled() {
                 let buf = nes.debug_get_overlay_buffer();
                 debug_texture
                     .update(None, buf, (WIDTH * 4) as usize)
                     .map_err(|e| e.to_string())?;
-                canvas.copy(&debug_texture, None, None)?;
+                // The overlay is in NES pixels, so crop it the same way.
+                let overscan = video.settings.overscan;
+                let (visible_width, visible_height) = video.settings.visible_size();
+                let visible = Rect::new(
+                    overscan.left as i32,
+                    overscan.top as i32,
+                    visible_width as u32,
+                    visible_height as u32,
+                );
+                canvas.copy(&debug_texture, visible, target)?;
             }
 
             canvas.present();
@@ -220,7 +474,7 @@ fn run_emulator(
         if Instant::now() - frame_timer > Duration::from_secs(1) {
             canvas
                 .window_mut()
-                .set_title(&format!("NES - FPS: {}", frame_counter))
+                .set_title(&format!("{} - FPS: {}", title, frame_counter))
                 .map_err(|e| e.to_string())?;
             frame_counter = 0;
             frame_timer = Instant::now();
@@ -235,15 +489,136 @@ fn main() {
         .author("Eli Lipsitz <eli.lipsitz@gmail.com>")
         .arg(
             clap::Arg::with_name("rom")
-                .help("Path to the rom file to use")
+                .help("Path to the rom file to use (may be in a .zip or .gz)")
                 .required(true)
                 .index(1),
         )
+        .arg(
+            clap::Arg::with_name("archive-entry")
+                .long("archive-entry")
+                .takes_value(true)
+                .help("File to load from a .zip with several roms"),
+        )
         .arg(
             clap::Arg::with_name("cpu-log")
                 .long("cpu-log")
                 .help("Print CPU execution log"),
         )
+        .arg(
+            clap::Arg::with_name("power-on")
+                .long("power-on")
+                .takes_value(true)
+                .help("Power-on memory contents: zero, ff, hardware, random, or random:<seed>"),
+        )
+        .arg(
+            clap::Arg::with_name("region")
+                .long("region")
+                .takes_value(true)
+                .possible_values(&["ntsc", "pal", "dendy"])
+                .help("Override the region detected from the rom"),
+        )
+        .arg(
+            clap::Arg::with_name("palette")
+                .long("palette")
+                .takes_value(true)
+                .help(
+                    "Colors: a .pal file, a built-in palette (default, ntsc, rgb), or ntsc: \
+                     followed by hue, saturation, contrast, brightness, gamma settings \
+                     (e.g. ntsc:hue=-10,saturation=1.2)",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("ntsc")
+                .long("ntsc")
+                .takes_value(true)
+                .possible_values(&nes_core::NTSC_PRESETS)
+                .help("Simulate an NTSC TV's video signal"),
+        )
+        .arg(
+            clap::Arg::with_name("no-sprite-limit")
+                .long("no-sprite-limit")
+                .help("Show every sprite on a line instead of eight, to reduce flicker"),
+        )
+        .arg(
+            clap::Arg::with_name("filter")
+                .long("filter")
+                .takes_value(true)
+                .possible_values(&nes_video::FILTERS)
+                .help("Upscale the picture with scale2x/3x, hq2x/3x/4x or xbr2x/3x/4x"),
+        )
+        .arg(
+            clap::Arg::with_name("overscan")
+                .long("overscan")
+                .takes_value(true)
+                .help(
+                    "Pixels to crop off the edges: one for all edges, vertical,horizontal, \
+                     or top,bottom,left,right",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("aspect")
+                .long("aspect")
+                .help("Stretch to the 8:7 pixel aspect ratio of a TV"),
+        )
+        .arg(
+            clap::Arg::with_name("integer-scaling")
+                .long("integer-scaling")
+                .help("Only scale the picture to the window by whole numbers"),
+        )
+        .arg(
+            clap::Arg::with_name("screenshot")
+                .long("screenshot")
+                .takes_value(true)
+                .help("Run without a window, and save a PNG of the picture after --frames frames"),
+        )
+        .arg(
+            clap::Arg::with_name("frames")
+                .long("frames")
+                .takes_value(true)
+                .default_value("60")
+                .help("Frames to run before --screenshot"),
+        )
+        .arg(
+            clap::Arg::with_name("port1")
+                .long("port1")
+                .takes_value(true)
+                .help("Device in controller port 1 (default: controller)"),
+        )
+        .arg(
+            clap::Arg::with_name("port2")
+                .long("port2")
+                .takes_value(true)
+                .help("Device in controller port 2 (default: controller)"),
+        )
+        .arg(
+            clap::Arg::with_name("expansion")
+                .long("expansion")
+                .takes_value(true)
+                .help("Device in the Famicom expansion port (default: none)"),
+        )
+        .arg(
+            clap::Arg::with_name("patch")
+                .long("patch")
+                .takes_value(true)
+                .help("Apply an IPS, BPS or UPS patch (default: a patch named like the rom)"),
+        )
+        .arg(
+            clap::Arg::with_name("gamedb")
+                .long("gamedb")
+                .takes_value(true)
+                .help(
+                    "Game database to correct rom headers with. Only a few games are built in, \
+                     so load a full database (e.g. converted from NesCartDB) for the rest",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("cheat")
+                .long("cheat")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Add a Game Genie code, saved to the rom's cheat file"),
+        )
         .arg(
             clap::Arg::with_name("audio-output")
                 .long("audio-output")
@@ -258,6 +633,19 @@ fn main() {
     let mut debug = nes_core::Debug::default();
     debug.cpu_log = args.is_present("cpu-log");
 
+    let power_on = match args.value_of("power-on") {
+        None => nes_core::PowerOnPattern::default(),
+        Some("random") => {
+            let seed = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos() as u64;
+            println!("[main] Power-on RAM seed: {}", seed);
+            nes_core::PowerOnPattern::Random(seed)
+        }
+        Some(pattern) => pattern.parse().unwrap_or_else(|e| panic!("{}", e)),
+    };
+
     let audio_out = args.value_of("audio-output").map(|filename| {
         let spec = hound::WavSpec {
             channels: 1,
@@ -268,11 +656,125 @@ fn main() {
         hound::WavWriter::create(filename, spec).unwrap()
     });
 
-    let rom_filename = Path::new(rom_path).file_name().unwrap().to_str().unwrap();
-    let save_state_path = format!("state_{}.nes_state", rom_filename);
+    let rom = archive::read_rom(Path::new(rom_path), args.value_of("archive-entry"))
+        .unwrap_or_else(|e| panic!("Error reading rom file: {}", e));
+    if rom.name != Path::new(rom_path).file_name().unwrap().to_string_lossy() {
+        println!("[main] Loading {} from the archive", rom.name);
+    }
+    let rom_filename = rom.name.as_str();
 
-    let cartridge_data = std::fs::read(rom_path).expect("Error reading rom file");
-    let cart = nes_core::Cartridge::load(&cartridge_data);
-    let mut nes = Box::new(nes_core::Nes::new(debug, cart));
-    run_emulator(nes.as_mut(), audio_out, &save_state_path).unwrap();
+    let mut cartridge_data = rom.data;
+    let rom_dir = Path::new(rom_path).parent().unwrap();
+    let patch_path = args.value_of("patch").map(PathBuf::from).or_else(|| {
+        ["ips", "bps", "ups"]
+            .iter()
+            .map(|ext| rom_dir.join(rom_filename).with_extension(ext))
+            .find(|path| path.exists())
+    });
+    if let Some(patch_path) = patch_path {
+        println!("[main] Applying patch: {}", patch_path.display());
+        let patch = std::fs::read(&patch_path).expect("Error reading patch file");
+        cartridge_data = nes_core::apply_patch(&cartridge_data, &patch)
+            .unwrap_or_else(|e| panic!("{}: {}", patch_path.display(), e));
+    }
+    let mut gamedb = nes_core::GameDb::embedded();
+    if let Some(path) = args.value_of("gamedb") {
+        let text = std::fs::read_to_string(path).expect("Error reading game database");
+        gamedb.extend(nes_core::GameDb::parse(&text).unwrap_or_else(|e| panic!("{}: {}", path, e)));
+    }
+    let cart = nes_core::Cartridge::load_with_db(&cartridge_data, &gamedb);
+
+    // Games in the database are named by title, so differently named dumps share saves.
+    let save_name = match cart.title() {
+        Some(title) => title
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || " .-_()'".contains(c) {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect(),
+        None => rom_filename.to_string(),
+    };
+    let save_state_path = format!("state_{}.nes_state", save_name);
+    let cheats_path = format!("cheats_{}.txt", save_name);
+    let battery_path = format!("{}.sav", save_name);
+    let mut nes = Box::new(nes_core::Nes::with_power_on(debug, cart, power_on));
+    if let Some(region) = args.value_of("region") {
+        nes.set_region(region.parse().unwrap());
+    }
+    load_battery_ram(&mut nes, &battery_path);
+    if let Some(palette) = args.value_of("palette") {
+        let palette = if Path::new(palette).is_file() {
+            let data = std::fs::read(palette).expect("Error reading palette file");
+            nes_core::Palette::from_pal(&data)
+        } else {
+            palette.parse()
+        };
+        nes.set_palette(palette.unwrap_or_else(|e| panic!("{}", e)));
+    }
+    if args.is_present("no-sprite-limit") {
+        nes.set_unlimited_sprites(true);
+    }
+    let ports = [
+        ("port1", nes_core::Port::One),
+        ("port2", nes_core::Port::Two),
+        ("expansion", nes_core::Port::Expansion),
+    ];
+    for &(arg, port) in ports.iter() {
+        if let Some(device) = args.value_of(arg) {
+            let kind = device.parse().unwrap_or_else(|e| panic!("{}", e));
+            nes.set_input_device(port, kind);
+        }
+    }
+    if let Ok(text) = std::fs::read_to_string(&cheats_path) {
+        nes.load_cheats_text(&text)
+            .unwrap_or_else(|e| panic!("{}: {}", cheats_path, e));
+        println!(
+            "[main] Loaded {} cheats from {}",
+            nes.get_cheats().len(),
+            cheats_path
+        );
+    }
+    if let Some(codes) = args.values_of("cheat") {
+        for code in codes {
+            nes.add_cheat(code).unwrap_or_else(|e| panic!("{}", e));
+        }
+        save_cheats(&nes, &cheats_path);
+    }
+    let ntsc = args.value_of("ntsc").map(|preset| {
+        let preset = preset.parse().unwrap_or_else(|e| panic!("{}", e));
+        nes_core::NtscFilter::new(preset, nes_core::NtscPalette::default())
+    });
+    let settings = nes_video::VideoSettings {
+        filter: args.value_of("filter").unwrap_or("none").parse().unwrap(),
+        overscan: match args.value_of("overscan") {
+            Some(overscan) => overscan.parse().unwrap_or_else(|e| panic!("{}", e)),
+            None => nes_video::Overscan::default(),
+        },
+        aspect_correction: args.is_present("aspect"),
+        integer_scaling: args.is_present("integer-scaling"),
+    };
+    let mut video = video::Video::new(nes.as_mut(), settings, ntsc);
+    if let Some(path) = args.value_of("screenshot") {
+        let frames = args
+            .value_of("frames")
+            .unwrap()
+            .parse()
+            .expect("--frames should be a number");
+        take_screenshot(nes.as_mut(), &mut video, frames, Path::new(path)).unwrap();
+        return;
+    }
+    run_emulator(
+        nes.as_mut(),
+        audio_out,
+        &save_state_path,
+        &cheats_path,
+        &save_name,
+        video,
+    )
+    .unwrap();
+    save_battery_ram(&mut nes, &battery_path);
 }
@@ -0,0 +1,74 @@
+use std::io::{BufRead, Write};
+
+use nes_core::parse_number;
+
+/// Candidates shown by "list" before truncating.
+const MAX_LISTED: usize = 64;
+
+const HELP: &str = "RAM search commands:
+  reset                 start a new search
+  eq | ne | gt | lt     keep values equal/changed/greater/less since the last step
+  <value>               keep values equal to <value>
+  list                  show the candidates
+  freeze <addr> <value> hold an address at a value every frame
+  unfreeze <addr>       stop holding an address
+  frozen                show the frozen addresses
+  (empty line)          resume emulation
+Numbers are decimal, or hex with a $ or 0x prefix.";
+
+/// Reads RAM search commands from stdin until an empty line. Emulation is paused meanwhile,
+/// so run a few frames between filters to let values change.
+pub fn prompt(nes: &mut nes_core::Nes) {
+    println!("{}", HELP);
+    let stdin = std::io::stdin();
+    loop {
+        print!("search> ");
+        std::io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            return;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => return,
+            ["reset"] => {
+                nes.ram_search_reset();
+                println!("{} candidates", nes.ram_search_candidates().len());
+            }
+            ["list"] => {
+                let candidates = nes.ram_search_candidates();
+                for (addr, value) in candidates.iter().take(MAX_LISTED) {
+                    println!("  ${:04X} = ${:02X} ({})", addr, value, value);
+                }
+                if candidates.len() > MAX_LISTED {
+                    println!("  ... and {} more", candidates.len() - MAX_LISTED);
+                }
+            }
+            ["freeze", addr, value] => match (parse_number(addr), parse_number(value)) {
+                (Some(addr), Some(value)) if value <= 0xFF => {
+                    if !nes.freeze_memory(addr, value as u8) {
+                        println!("${:04X} isn't RAM", addr);
+                    }
+                }
+                _ => println!("usage: freeze <addr> <value>"),
+            },
+            ["unfreeze", addr] => match parse_number(addr) {
+                Some(addr) => nes.unfreeze_memory(addr),
+                None => println!("usage: unfreeze <addr>"),
+            },
+            ["frozen"] => {
+                for (addr, value) in nes.get_frozen_memory() {
+                    println!("  ${:04X} = ${:02X} ({})", addr, value, value);
+                }
+            }
+            [filter] => match filter.parse() {
+                Ok(filter) => {
+                    nes.ram_search_filter(filter);
+                    println!("{} candidates", nes.ram_search_candidates().len());
+                }
+                Err(e) => println!("{}", e),
+            },
+            _ => println!("{}", HELP),
+        }
+    }
+}
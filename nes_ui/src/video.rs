@@ -0,0 +1,61 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use nes_video::{Image, VideoSettings};
+
+/// Turns the emulator's frames into the picture that's shown or saved.
+pub struct Video {
+    pub settings: VideoSettings,
+    ntsc: Option<nes_core::NtscFilter>,
+    ntsc_buffer: Vec<u8>,
+}
+
+impl Video {
+    pub fn new(
+        nes: &mut nes_core::Nes,
+        settings: VideoSettings,
+        ntsc: Option<nes_core::NtscFilter>,
+    ) -> Video {
+        // The NTSC filter only needs the indexed frame.
+        if ntsc.is_some() {
+            nes.set_rgba_output(false);
+        }
+        Video {
+            settings,
+            ntsc,
+            ntsc_buffer: vec![0; nes_core::NTSC_WIDTH * nes_core::FRAME_HEIGHT * 4],
+        }
+    }
+
+    /// The last frame, filtered.
+    pub fn render(&mut self, nes: &nes_core::Nes) -> Image {
+        match &self.ntsc {
+            Some(filter) => {
+                filter.apply(
+                    nes.get_index_buffer(),
+                    nes.get_scanline_phases(),
+                    &mut self.ntsc_buffer,
+                );
+                self.settings
+                    .process(&self.ntsc_buffer, nes_core::NTSC_WIDTH)
+            }
+            None => self
+                .settings
+                .process(nes.get_frame_buffer(), nes_core::FRAME_WIDTH),
+        }
+    }
+}
+
+pub fn save_png(path: &Path, image: &Image) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        image.width as u32,
+        image.height as u32,
+    );
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&image.to_rgba()))
+        .map_err(|e| e.to_string())
+}
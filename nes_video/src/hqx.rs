@@ -0,0 +1,387 @@
+use super::image::{yuv, Image};
+
+/// Whether two pixels look different, by hqx's thresholds on brightness and color.
+fn differ(a: u32, b: u32) -> bool {
+    let (y1, u1, v1) = yuv(a);
+    let (y2, u2, v2) = yuv(b);
+    (y1 - y2).abs() > 0x30 || (u1 - u2).abs() > 0x07 || (v1 - v2).abs() > 0x06
+}
+
+/// The pixels around E, seen from its top left corner:
+///
+/// ```text
+///     A B C
+///     D E F
+///     G H I
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Src {
+    E,
+    A,
+    B,
+    D,
+    F,
+    H,
+}
+
+use Src::*;
+
+/// Up to three pixels mixed by weight, in sixteenths.
+type Mix = [(Src, u32); 3];
+
+const KEEP: Mix = [(E, 16), (E, 0), (E, 0)];
+
+/// 3:1 of E and `x`.
+fn interp1(x: Src) -> Mix {
+    [(E, 12), (x, 4), (E, 0)]
+}
+
+/// 1:3 of E and `x`.
+fn interp1_toward(x: Src) -> Mix {
+    [(E, 4), (x, 12), (E, 0)]
+}
+
+/// 2:1:1 of E, `x` and `y`.
+fn interp2(x: Src, y: Src) -> Mix {
+    [(E, 8), (x, 4), (y, 4)]
+}
+
+/// 7:1 of E and `x`.
+fn interp3(x: Src) -> Mix {
+    [(E, 14), (x, 2), (E, 0)]
+}
+
+/// 2:7:7 of E, `x` and `y`.
+fn interp4(x: Src, y: Src) -> Mix {
+    [(E, 2), (x, 7), (y, 7)]
+}
+
+/// 1:1 of E and `x`.
+fn interp5(x: Src) -> Mix {
+    [(E, 8), (x, 8), (E, 0)]
+}
+
+/// 1:1 of `x` and `y`, leaving E out.
+fn interp5_across(x: Src, y: Src) -> Mix {
+    [(E, 0), (x, 8), (y, 8)]
+}
+
+/// 5:2:1 of E, `x` and `y`.
+fn interp6(x: Src, y: Src) -> Mix {
+    [(E, 10), (x, 4), (y, 2)]
+}
+
+/// 6:1:1 of E, `x` and `y`.
+fn interp7(x: Src, y: Src) -> Mix {
+    [(E, 12), (x, 2), (y, 2)]
+}
+
+/// 5:3 of E and `x`.
+fn interp8(x: Src) -> Mix {
+    [(E, 10), (x, 6), (E, 0)]
+}
+
+/// 2:3:3 of E, `x` and `y`.
+fn interp9(x: Src, y: Src) -> Mix {
+    [(E, 4), (x, 6), (y, 6)]
+}
+
+/// 14:1:1 of E, `x` and `y`.
+fn interp10(x: Src, y: Src) -> Mix {
+    [(E, 14), (x, 1), (y, 1)]
+}
+
+/// The rule for a pixel's top left corner, by which of its neighbors differ from it: bit 0 for
+/// A, 1 for B, 2 for C, 3 for D, 4 for F, 5 for G, 6 for H and 7 for I. The other corners use
+/// the same table with the neighborhood turned. The rules are numbered as in `corner`.
+#[rustfmt::skip]
+const RULES: [u8; 256] = [
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 15, 12, 5,  3, 17, 13,
+    4, 4, 6, 18, 4, 4, 6, 18, 5,  3, 12, 12, 5,  3,  1, 12,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 17, 13, 5,  3, 17, 13,
+    4, 4, 6, 18, 4, 4, 6, 18, 5,  3, 16, 12, 5,  3,  1, 14,
+    4, 4, 6,  2, 4, 4, 6,  2, 5, 19, 12, 12, 5, 19, 16, 12,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 16, 12, 5,  3, 16, 12,
+    4, 4, 6,  2, 4, 4, 6,  2, 5, 19,  1, 12, 5, 19,  1, 14,
+    4, 4, 6,  2, 4, 4, 6, 18, 5,  3, 16, 12, 5, 19,  1, 14,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 15, 12, 5,  3, 17, 13,
+    4, 4, 6, 18, 4, 4, 6, 18, 5,  3, 12, 12, 5,  3,  1, 12,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 17, 13, 5,  3, 17, 13,
+    4, 4, 6, 18, 4, 4, 6, 18, 5,  3, 16, 12, 5,  3,  1, 14,
+    4, 4, 6,  2, 4, 4, 6,  2, 5, 19, 12, 12, 5, 19, 16, 12,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 16, 12, 5,  3, 16, 12,
+    4, 4, 6,  2, 4, 4, 6,  2, 5, 19,  1, 12, 5, 19,  1, 14,
+    4, 4, 6,  2, 4, 4, 6, 18, 5,  3, 16, 12, 5, 19,  1, 14,
+];
+
+/// The neighborhood (indices into A B C D E F G H I) turned so each corner in turn is at the
+/// top left: top left, top right, bottom right, bottom left.
+const TURNS: [[usize; 9]; 4] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8],
+    [2, 5, 8, 1, 4, 7, 0, 3, 6],
+    [8, 7, 6, 5, 4, 3, 2, 1, 0],
+    [6, 3, 0, 7, 4, 1, 8, 5, 2],
+];
+
+/// Where a subpixel at (u, v) from a corner is in the pixel's `factor` x `factor` block.
+fn place(turn: usize, factor: usize, u: usize, v: usize) -> (usize, usize) {
+    let last = factor - 1;
+    match turn {
+        0 => (u, v),
+        1 => (last - v, u),
+        2 => (last - u, last - v),
+        _ => (v, last - u),
+    }
+}
+
+/// The subpixels at a pixel's top left corner: the corner itself, the next ones along the top
+/// and left edges, and the one inside them. 2x fills only the corner; in 3x the edge subpixels
+/// are the middles of the sides, shared with the next corner.
+fn corner(rule: u8, factor: usize, same: impl Fn(Src, Src) -> bool) -> [Mix; 4] {
+    let corner_only = (
+        interp1(A),
+        [interp1(A), KEEP, KEEP],
+        [interp8(A), interp1(A), interp1(A), interp3(A)],
+    );
+    let keep = (KEEP, [KEEP; 3], [KEEP; 4]);
+    let (two, three, four) = match rule {
+        1 => corner_only,
+        2 => (
+            interp1(D),
+            [interp1(D), KEEP, interp1(D)],
+            [interp8(D), interp3(D), interp8(D), interp3(D)],
+        ),
+        3 => (
+            interp1(B),
+            [interp1(B), interp1(B), KEEP],
+            [interp8(B), interp8(B), interp3(B), interp3(B)],
+        ),
+        4 => (
+            interp2(D, B),
+            [interp2(D, B), interp1(B), interp1(D)],
+            [interp2(D, B), interp6(B, D), interp6(D, B), interp7(B, D)],
+        ),
+        5 => (
+            interp2(A, B),
+            [interp1(A), interp1(B), KEEP],
+            [interp2(A, B), interp6(B, A), interp1(A), interp7(A, B)],
+        ),
+        6 => (
+            interp2(A, D),
+            [interp1(A), KEEP, interp1(D)],
+            [interp2(A, D), interp1(A), interp6(D, A), interp7(A, D)],
+        ),
+        // B and D both differ from E. If they match each other, there's a diagonal edge across
+        // the corner.
+        12..=14 if !same(B, D) => keep,
+        15..=17 if !same(B, D) => corner_only,
+        12 => (
+            interp2(D, B),
+            [interp4(D, B), interp3(B), interp3(D)],
+            [interp5_across(D, B), interp5(B), interp5(D), KEEP],
+        ),
+        13 => (
+            interp10(D, B),
+            [interp7(D, B), KEEP, KEEP],
+            [interp7(D, B), KEEP, KEEP, KEEP],
+        ),
+        14 | 16 => (
+            interp7(D, B),
+            [interp2(D, B), interp3(B), interp3(D)],
+            [interp2(D, B), interp3(B), interp3(D), KEEP],
+        ),
+        15 => (
+            interp2(D, B),
+            [interp2(D, B), interp1(B), interp1(D)],
+            [interp2(D, B), interp1(B), interp1(D), KEEP],
+        ),
+        17 => (
+            interp9(D, B),
+            [interp4(D, B), interp1(B), interp1(D)],
+            [interp5_across(D, B), interp5(B), interp5(D), KEEP],
+        ),
+        // A shallow edge from B to F, or a steep one from D to H, runs on into this corner.
+        18 if same(B, F) => (
+            interp6(B, D),
+            [interp6(B, D), interp1_toward(B), interp1(D)],
+            [interp1(B), interp1_toward(B), interp8(D), interp3(D)],
+        ),
+        18 => return corner(2, factor, same),
+        19 if same(D, H) => (
+            interp6(D, B),
+            [interp6(D, B), interp1(B), interp1_toward(D)],
+            [interp1(D), interp8(B), interp1_toward(D), interp3(B)],
+        ),
+        19 => return corner(3, factor, same),
+        _ => keep,
+    };
+    match factor {
+        2 => [two, KEEP, KEEP, KEEP],
+        3 => [three[0], three[1], three[2], KEEP],
+        _ => four,
+    }
+}
+
+fn mix(weights: Mix, pixel: impl Fn(Src) -> u32) -> u32 {
+    let mut out = 0;
+    for shift in (0..32).step_by(8) {
+        let sum: u32 = weights
+            .iter()
+            .map(|&(src, weight)| ((pixel(src) >> shift) & 0xFF) * weight)
+            .sum();
+        out |= ((sum + 8) / 16) << shift;
+    }
+    out
+}
+
+/// How far apart two pixels are, adding up the differences of their channels.
+fn distance(a: u32, b: u32) -> u32 {
+    a.to_le_bytes()
+        .iter()
+        .zip(b.to_le_bytes().iter())
+        .map(|(&x, &y)| (x as i32 - y as i32).unsigned_abs())
+        .sum()
+}
+
+/// HQ2x, HQ3x and HQ4x. Each of a pixel's eight neighbors is marked as different or not by
+/// hqx's YUV thresholds, and that 256-way pattern picks a rule for each corner: which
+/// neighbors to blend in, and by how much, possibly depending on whether two of the neighbors
+/// match each other. Each factor spreads the rule over its own subpixels.
+/// https://en.wikipedia.org/wiki/Hqx
+pub fn hqx(image: &Image, factor: usize) -> Image {
+    let mut out = Image::new(image.width * factor, image.height * factor);
+    let mut block = vec![0; factor * factor];
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let (ix, iy) = (x as isize, y as isize);
+            let mut neighbors = [0; 9];
+            for (i, pixel) in neighbors.iter_mut().enumerate() {
+                *pixel = image.get(ix + (i % 3) as isize - 1, iy + (i / 3) as isize - 1);
+            }
+            let e = neighbors[4];
+            for pixel in block.iter_mut() {
+                *pixel = e;
+            }
+            for (turn, order) in TURNS.iter().enumerate() {
+                let turned = order.map(|i| neighbors[i]);
+                let pattern = [0, 1, 2, 3, 5, 6, 7, 8]
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &i)| differ(e, turned[i]))
+                    .fold(0, |pattern, (bit, _)| pattern | 1 << bit);
+                let pixel = |src: Src| match src {
+                    E => turned[4],
+                    A => turned[0],
+                    B => turned[1],
+                    D => turned[3],
+                    F => turned[5],
+                    H => turned[7],
+                };
+                let same = |a: Src, b: Src| !differ(pixel(a), pixel(b));
+                let mixes = corner(RULES[pattern], factor, same);
+                let used = match factor {
+                    2 => 1,
+                    3 => 3,
+                    _ => 4,
+                };
+                let positions = [(0, 0), (1, 0), (0, 1), (1, 1)];
+                for (&weights, &(u, v)) in mixes.iter().zip(positions.iter()).take(used) {
+                    let (bx, by) = place(turn, factor, u, v);
+                    let color = mix(weights, pixel);
+                    // The middles of a 3x block's sides are shared by two corners. Keep
+                    // whichever blends in more.
+                    let current = &mut block[by * factor + bx];
+                    if distance(color, e) > distance(*current, e) {
+                        *current = color;
+                    }
+                }
+            }
+            for (i, &pixel) in block.iter().enumerate() {
+                out.set(x * factor + i % factor, y * factor + i / factor, pixel);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const W: u32 = 0xFFFF_FFFF;
+    const K: u32 = 0xFF00_0000;
+
+    /// White with a black diagonal edge across the bottom right corner.
+    fn diagonal() -> Image {
+        let mut image = Image::new(3, 3);
+        image.pixels = vec![W, W, W, W, W, K, W, K, K];
+        image
+    }
+
+    fn block(image: &Image, x: usize, y: usize, factor: usize) -> Vec<u32> {
+        let mut pixels = Vec::new();
+        for v in 0..factor {
+            for u in 0..factor {
+                pixels.push(image.get((x * factor + u) as isize, (y * factor + v) as isize));
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn rules_are_symmetric_across_the_diagonal() {
+        // Mirroring across the top left to bottom right diagonal swaps B with D, C with G and
+        // F with H, and the rules that lean on one side with the other.
+        let swaps = [(1, 3), (2, 5), (4, 6)];
+        for (pattern, &rule) in RULES.iter().enumerate() {
+            let mut mirrored = pattern & 0b1000_0001;
+            for &(a, b) in swaps.iter() {
+                mirrored |= (pattern >> a & 1) << b | (pattern >> b & 1) << a;
+            }
+            let expected = match rule {
+                2 => 3,
+                3 => 2,
+                5 => 6,
+                6 => 5,
+                18 => 19,
+                19 => 18,
+                rule => rule,
+            };
+            assert_eq!(RULES[mirrored], expected, "pattern {:08b}", pattern);
+        }
+    }
+
+    #[test]
+    fn hq2x_blends_a_diagonal_edge() {
+        let out = hqx(&diagonal(), 2);
+        assert_eq!((out.width, out.height), (6, 6));
+        assert_eq!(block(&out, 1, 1, 2), [W, W, W, 0xFF80_8080]);
+        assert_eq!(block(&out, 0, 0, 2), [W; 4]);
+    }
+
+    #[test]
+    fn hq3x_blends_a_diagonal_edge() {
+        let out = hqx(&diagonal(), 3);
+        assert_eq!((out.width, out.height), (9, 9));
+        let edge = 0xFFDF_DFDF;
+        assert_eq!(
+            block(&out, 1, 1, 3),
+            [W, W, W, W, W, edge, W, edge, 0xFF20_2020]
+        );
+    }
+
+    #[test]
+    fn hq4x_blends_a_diagonal_edge() {
+        let out = hqx(&diagonal(), 4);
+        assert_eq!((out.width, out.height), (12, 12));
+        let half = 0xFF80_8080;
+        #[rustfmt::skip]
+        let expected = [
+            W, W, W, W,
+            W, W, W, W,
+            W, W, W, half,
+            W, W, half, K,
+        ];
+        assert_eq!(block(&out, 1, 1, 4), expected);
+    }
+}
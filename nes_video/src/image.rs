@@ -0,0 +1,106 @@
+/// An RGBA picture. Each pixel is its 4 bytes read as a little-endian u32, so red is the low
+/// byte.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    pub fn from_rgba(data: &[u8], width: usize, height: usize) -> Image {
+        let pixels = data
+            .chunks_exact(4)
+            .take(width * height)
+            .map(|p| u32::from_le_bytes([p[0], p[1], p[2], p[3]]))
+            .collect();
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn to_rgba(&self) -> Vec<u8> {
+        self.pixels.iter().flat_map(|p| p.to_le_bytes()).collect()
+    }
+
+    /// The pixel at (x, y), with coordinates off the edge clamped to it.
+    pub fn get(&self, x: isize, y: isize) -> u32 {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, pixel: u32) {
+        self.pixels[y * self.width + x] = pixel;
+    }
+
+    /// The part of the image starting at (x, y).
+    pub fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> Image {
+        let mut out = Image::new(width, height);
+        for row in 0..height {
+            let start = (y + row) * self.width + x;
+            out.pixels[row * width..(row + 1) * width]
+                .copy_from_slice(&self.pixels[start..start + width]);
+        }
+        out
+    }
+
+    /// Stretches the image horizontally, interpolating between pixels.
+    pub fn resize_width(&self, width: usize) -> Image {
+        if width == self.width {
+            return self.clone();
+        }
+        let mut out = Image::new(width, self.height);
+        let scale = self.width as f32 / width as f32;
+        for x in 0..width {
+            // Sample at the output pixel's center.
+            let pos = ((x as f32 + 0.5) * scale - 0.5).max(0.0);
+            let left = pos as isize;
+            let weight = pos - left as f32;
+            for y in 0..self.height {
+                let (y, a, b) = (y as isize, left, left + 1);
+                let pixel = blend(self.get(a, y), self.get(b, y), weight);
+                out.set(x, y as usize, pixel);
+            }
+        }
+        out
+    }
+}
+
+/// Mixes `b` into `a` by `weight`, from 0 (all `a`) to 1 (all `b`).
+pub fn blend(a: u32, b: u32, weight: f32) -> u32 {
+    if weight <= 0.0 {
+        return a;
+    }
+    if weight >= 1.0 {
+        return b;
+    }
+    let mut out = 0;
+    for shift in (0..32).step_by(8) {
+        let x = ((a >> shift) & 0xFF) as f32;
+        let y = ((b >> shift) & 0xFF) as f32;
+        out |= ((x + (y - x) * weight).round() as u32) << shift;
+    }
+    out
+}
+
+/// The pixel's brightness and color difference, as the hqx and xBR filters compare them.
+pub fn yuv(pixel: u32) -> (i32, i32, i32) {
+    let r = (pixel & 0xFF) as i32;
+    let g = ((pixel >> 8) & 0xFF) as i32;
+    let b = ((pixel >> 16) & 0xFF) as i32;
+    let y = (r * 299 + g * 587 + b * 114) / 1000;
+    let u = (-r * 169 - g * 331 + b * 500) / 1000 + 128;
+    let v = (r * 500 - g * 419 - b * 81) / 1000 + 128;
+    (y, u, v)
+}
@@ -0,0 +1,238 @@
+mod hqx;
+mod image;
+mod scale2x;
+mod xbr;
+
+use std::str::FromStr;
+
+use nes_core::{FRAME_HEIGHT, FRAME_WIDTH};
+
+pub use image::Image;
+
+/// Names accepted by `Filter::from_str`.
+pub const FILTERS: [&str; 9] = [
+    "none", "scale2x", "scale3x", "hq2x", "hq3x", "hq4x", "xbr2x", "xbr3x", "xbr4x",
+];
+
+/// An upscaling filter for pixel art.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Filter {
+    #[default]
+    None,
+    Scale2x,
+    Scale3x,
+    Hqx(usize),
+    Xbr(usize),
+}
+
+impl Filter {
+    /// How many times bigger the filter makes the picture.
+    pub fn factor(&self) -> usize {
+        match *self {
+            Filter::None => 1,
+            Filter::Scale2x => 2,
+            Filter::Scale3x => 3,
+            Filter::Hqx(factor) | Filter::Xbr(factor) => factor,
+        }
+    }
+
+    pub fn apply(&self, image: &Image) -> Image {
+        match *self {
+            Filter::None => image.clone(),
+            Filter::Scale2x => scale2x::scale2x(image),
+            Filter::Scale3x => scale2x::scale3x(image),
+            Filter::Hqx(factor) => hqx::hqx(image, factor),
+            Filter::Xbr(factor) => xbr::xbr(image, factor),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Filter::None),
+            "scale2x" => Ok(Filter::Scale2x),
+            "scale3x" => Ok(Filter::Scale3x),
+            "hq2x" => Ok(Filter::Hqx(2)),
+            "hq3x" => Ok(Filter::Hqx(3)),
+            "hq4x" => Ok(Filter::Hqx(4)),
+            "xbr2x" => Ok(Filter::Xbr(2)),
+            "xbr3x" => Ok(Filter::Xbr(3)),
+            "xbr4x" => Ok(Filter::Xbr(4)),
+            _ => Err(format!(
+                "unknown filter: {} (filters: {})",
+                s,
+                FILTERS.join(", ")
+            )),
+        }
+    }
+}
+
+/// Pixels to cut off each edge of the picture, which TVs hid behind their bezels.
+/// https://wiki.nesdev.com/w/index.php/Overscan
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Overscan {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+impl FromStr for Overscan {
+    type Err = String;
+
+    /// Parses one number for every edge, two for top and bottom then left and right, or four
+    /// for top, bottom, left and right.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse())
+            .collect::<Result<Vec<usize>, _>>()
+            .map_err(|_| format!("invalid overscan: {}", s))?;
+        let overscan = match *values.as_slice() {
+            [all] => Overscan {
+                top: all,
+                bottom: all,
+                left: all,
+                right: all,
+            },
+            [vertical, horizontal] => Overscan {
+                top: vertical,
+                bottom: vertical,
+                left: horizontal,
+                right: horizontal,
+            },
+            [top, bottom, left, right] => Overscan {
+                top,
+                bottom,
+                left,
+                right,
+            },
+            _ => return Err(format!("invalid overscan: {}", s)),
+        };
+        if overscan.top + overscan.bottom >= FRAME_HEIGHT
+            || overscan.left + overscan.right >= FRAME_WIDTH
+        {
+            return Err(format!("overscan crops the whole picture: {}", s));
+        }
+        Ok(overscan)
+    }
+}
+
+/// How frames are turned into the picture that's shown or saved.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct VideoSettings {
+    pub filter: Filter,
+    pub overscan: Overscan,
+    /// Stretch the picture to the 8:7 pixel aspect ratio of an NTSC TV.
+    pub aspect_correction: bool,
+    /// Only scale the picture to the window by whole numbers, so every pixel is the same size.
+    pub integer_scaling: bool,
+}
+
+impl VideoSettings {
+    /// Filters a frame. Its width may be a multiple of the NES's 256 pixels, like the NTSC
+    /// filter's; overscan is always in NES pixels.
+    pub fn process(&self, rgba: &[u8], width: usize) -> Image {
+        let image = Image::from_rgba(rgba, width, FRAME_HEIGHT);
+        let (visible_width, visible_height) = self.visible_size();
+        let scale_x = width / FRAME_WIDTH;
+        let image = image.crop(
+            self.overscan.left * scale_x,
+            self.overscan.top,
+            visible_width * scale_x,
+            visible_height,
+        );
+        let image = self.filter.apply(&image);
+        let (output_width, _) = self.output_size(width);
+        image.resize_width(output_width)
+    }
+
+    /// The size of the picture in NES pixels, after cropping.
+    pub fn visible_size(&self) -> (usize, usize) {
+        (
+            FRAME_WIDTH - self.overscan.left - self.overscan.right,
+            FRAME_HEIGHT - self.overscan.top - self.overscan.bottom,
+        )
+    }
+
+    /// The size of the images `process` makes from frames `input_width` wide.
+    pub fn output_size(&self, input_width: usize) -> (usize, usize) {
+        let (width, height) = self.visible_size();
+        let width = width * (input_width / FRAME_WIDTH);
+        let factor = self.filter.factor();
+        let width = if self.aspect_correction {
+            (width * factor * 8 + 3) / 7
+        } else {
+            width * factor
+        };
+        (width, height * factor)
+    }
+
+    /// The shape the picture is shown at, in NES pixels times the filter's factor. Wider
+    /// frames, like the NTSC filter's, are squeezed back into it.
+    pub fn display_size(&self) -> (usize, usize) {
+        self.output_size(FRAME_WIDTH)
+    }
+
+    /// Where to draw a picture of `display_size`'s shape in a window, as (x, y, width, height).
+    pub fn fit(&self, window_width: usize, window_height: usize) -> (i32, i32, u32, u32) {
+        let (width, height) = self.display_size();
+        let scale = f32::min(
+            window_width as f32 / width as f32,
+            window_height as f32 / height as f32,
+        );
+        let scale = if self.integer_scaling {
+            scale.floor().max(1.0)
+        } else {
+            scale
+        };
+        let (w, h) = (
+            (width as f32 * scale).round() as i32,
+            (height as f32 * scale).round() as i32,
+        );
+        (
+            (window_width as i32 - w) / 2,
+            (window_height as i32 - h) / 2,
+            w as u32,
+            h as u32,
+        )
+    }
+
+    /// The NES pixel under a point in a window, which may be off the picture.
+    pub fn to_frame(&self, window_size: (usize, usize), x: i32, y: i32) -> (i32, i32) {
+        let (left, top, width, height) = self.fit(window_size.0, window_size.1);
+        let (visible_width, visible_height) = self.visible_size();
+        let frame_x = (x - left) as f32 * visible_width as f32 / width as f32;
+        let frame_y = (y - top) as f32 * visible_height as f32 / height as f32;
+        (
+            frame_x.floor() as i32 + self.overscan.left as i32,
+            frame_y.floor() as i32 + self.overscan.top as i32,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wide_frames_keep_their_columns() {
+        let settings = VideoSettings {
+            overscan: Overscan {
+                top: 8,
+                bottom: 8,
+                left: 8,
+                right: 0,
+            },
+            ..VideoSettings::default()
+        };
+        let frame = vec![0; FRAME_WIDTH * 2 * FRAME_HEIGHT * 4];
+        let image = settings.process(&frame, FRAME_WIDTH * 2);
+        assert_eq!((image.width, image.height), (496, 224));
+        assert_eq!(settings.output_size(FRAME_WIDTH * 2), (496, 224));
+        assert_eq!(settings.display_size(), (248, 224));
+    }
+}
@@ -0,0 +1,85 @@
+use super::image::Image;
+
+/// Scale2x (also known as EPX): each pixel becomes 2x2, with corners taking the color of
+/// matching neighbors so diagonal edges stay sharp.
+/// https://www.scale2x.it/algorithm
+pub fn scale2x(image: &Image) -> Image {
+    let mut out = Image::new(image.width * 2, image.height * 2);
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let (ix, iy) = (x as isize, y as isize);
+            let b = image.get(ix, iy - 1);
+            let d = image.get(ix - 1, iy);
+            let e = image.get(ix, iy);
+            let f = image.get(ix + 1, iy);
+            let h = image.get(ix, iy + 1);
+            let mut block = [e; 4];
+            if b != h && d != f {
+                block = [
+                    if d == b { d } else { e },
+                    if b == f { f } else { e },
+                    if d == h { d } else { e },
+                    if h == f { f } else { e },
+                ];
+            }
+            for (i, &pixel) in block.iter().enumerate() {
+                out.set(x * 2 + i % 2, y * 2 + i / 2, pixel);
+            }
+        }
+    }
+    out
+}
+
+/// Scale3x: Scale2x's rules extended to 3x3, with the edge pixels checking the corners too.
+/// https://www.scale2x.it/algorithm
+pub fn scale3x(image: &Image) -> Image {
+    let mut out = Image::new(image.width * 3, image.height * 3);
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let (ix, iy) = (x as isize, y as isize);
+            let a = image.get(ix - 1, iy - 1);
+            let b = image.get(ix, iy - 1);
+            let c = image.get(ix + 1, iy - 1);
+            let d = image.get(ix - 1, iy);
+            let e = image.get(ix, iy);
+            let f = image.get(ix + 1, iy);
+            let g = image.get(ix - 1, iy + 1);
+            let h = image.get(ix, iy + 1);
+            let i = image.get(ix + 1, iy + 1);
+            let mut block = [e; 9];
+            if b != h && d != f {
+                block = [
+                    if d == b { d } else { e },
+                    if (d == b && e != c) || (b == f && e != a) {
+                        b
+                    } else {
+                        e
+                    },
+                    if b == f { f } else { e },
+                    if (d == b && e != g) || (d == h && e != a) {
+                        d
+                    } else {
+                        e
+                    },
+                    e,
+                    if (b == f && e != i) || (h == f && e != c) {
+                        f
+                    } else {
+                        e
+                    },
+                    if d == h { d } else { e },
+                    if (d == h && e != i) || (h == f && e != g) {
+                        h
+                    } else {
+                        e
+                    },
+                    if h == f { f } else { e },
+                ];
+            }
+            for (n, &pixel) in block.iter().enumerate() {
+                out.set(x * 3 + n % 3, y * 3 + n / 3, pixel);
+            }
+        }
+    }
+    out
+}
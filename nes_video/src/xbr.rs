@@ -0,0 +1,83 @@
+use super::image::{blend, yuv, Image};
+
+/// How far a subpixel is from the center of its pixel, from -1 to 1.
+fn subpixel_offset(k: usize, factor: usize) -> f32 {
+    (k as f32 + 0.5) / factor as f32 * 2.0 - 1.0
+}
+
+/// How much of a cut-off corner covers the subpixel at distances `dx` and `dy` (0 to 1)
+/// toward that corner.
+fn corner_weight(dx: f32, dy: f32) -> f32 {
+    (dx + dy - 0.5).clamp(0.0, 1.0)
+}
+
+/// How different two pixels look, weighting brightness most.
+fn distance(a: u32, b: u32) -> i32 {
+    let (y1, u1, v1) = yuv(a);
+    let (y2, u2, v2) = yuv(b);
+    48 * (y1 - y2).abs() + 7 * (u1 - u2).abs() + 6 * (v1 - v2).abs()
+}
+
+/// The color to round the corner of (x, y) toward (sx, sy) with, if there's an edge across
+/// it. This is xBR's level 1 rule, written for the bottom right corner:
+///
+/// ```text
+///        A1 B1 C1
+///     A0 A  B  C  C4
+///     D0 D  E  F  F4
+///     G0 G  H  I  I4
+///        G5 H5 I5
+/// ```
+///
+/// The corner is cut when the edge along H-F is weaker than the one across E-I.
+fn corner(image: &Image, x: isize, y: isize, sx: isize, sy: isize) -> Option<u32> {
+    let at = |dx: isize, dy: isize| image.get(x + dx * sx, y + dy * sy);
+    let (b, c, d) = (at(0, -1), at(1, -1), at(-1, 0));
+    let (e, f, g) = (at(0, 0), at(1, 0), at(-1, 1));
+    let (h, i) = (at(0, 1), at(1, 1));
+    let (f4, i4, h5, i5) = (at(2, 0), at(2, 1), at(0, 2), at(1, 2));
+
+    let along =
+        distance(e, c) + distance(e, g) + distance(i, f4) + distance(i, h5) + 4 * distance(h, f);
+    let across =
+        distance(h, d) + distance(h, i5) + distance(f, i4) + distance(f, b) + 4 * distance(e, i);
+    if along < across {
+        Some(if distance(e, f) <= distance(e, h) {
+            f
+        } else {
+            h
+        })
+    } else {
+        None
+    }
+}
+
+/// xBR scaling by 2, 3 or 4: finds edges by comparing how strongly colors change along and
+/// across each corner's diagonal, and rounds off the corners the edges cut.
+/// https://forums.libretro.com/t/xbr-algorithm-tutorial/123
+pub fn xbr(image: &Image, factor: usize) -> Image {
+    let mut out = Image::new(image.width * factor, image.height * factor);
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let (ix, iy) = (x as isize, y as isize);
+            let e = image.get(ix, iy);
+            // Each corner, as (sx, sy), and the color to blend toward there.
+            let corners =
+                [(-1, -1), (1, -1), (-1, 1), (1, 1)].map(|(sx, sy)| corner(image, ix, iy, sx, sy));
+            for sy in 0..factor {
+                for sx in 0..factor {
+                    let (u, v) = (subpixel_offset(sx, factor), subpixel_offset(sy, factor));
+                    let index = (v > 0.0) as usize * 2 + (u > 0.0) as usize;
+                    let pixel = match corners[index] {
+                        Some(color) if u != 0.0 && v != 0.0 => {
+                            blend(e, color, corner_weight(u.abs(), v.abs()))
+                        }
+                        _ => e,
+                    };
+                    out.set(x * factor + sx, y * factor + sy, pixel);
+                }
+            }
+        }
+    }
+    out
+}
@@ -24,14 +24,132 @@ impl Emulator {
         self.nes.emulate_frame();
     }
 
+    /// The game's title, if it's in the game database.
+    pub fn title(&self) -> Option<String> {
+        self.nes.title().map(String::from)
+    }
+
+    pub fn reset(&mut self) {
+        self.nes.reset();
+    }
+
+    pub fn power_cycle(&mut self) {
+        self.nes.power_cycle();
+    }
+
+    /// Sets the memory contents for the next power cycle. Returns false if the pattern is invalid.
+    pub fn set_power_on_pattern(&mut self, pattern: &str) -> bool {
+        match pattern.parse() {
+            Ok(pattern) => {
+                self.nes.set_power_on_pattern(pattern);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
     pub fn get_frame_buffer(&self, out: &mut [u8]) {
         out.copy_from_slice(self.nes.get_frame_buffer());
     }
 
+    /// The frame as 9-bit pixels: emphasis in bits 6-8 and the palette index below.
+    pub fn get_index_buffer(&self, out: &mut [u16]) {
+        out.copy_from_slice(self.nes.get_index_buffer());
+    }
+
+    /// Skips converting frames to RGBA, when only `get_index_buffer` is used.
+    pub fn set_rgba_output(&mut self, enabled: bool) {
+        self.nes.set_rgba_output(enabled);
+    }
+
+    /// Shows every sprite on a line instead of eight. The game still sees the real limit.
+    pub fn set_unlimited_sprites(&mut self, enabled: bool) {
+        self.nes.set_unlimited_sprites(enabled);
+    }
+
+    /// Audio samples produced per frame, which depends on the region.
+    pub fn audio_samples_per_frame(&self) -> usize {
+        self.nes.get_audio_buffer().len()
+    }
+
     pub fn get_audio_buffer(&self, out: &mut [f32]) {
         out.copy_from_slice(self.nes.get_audio_buffer());
     }
 
+    /// Plugs a device (e.g. "controller", "none") into port 1, 2, or 0 for the Famicom
+    /// expansion port. Returns false if the device is unknown.
+    pub fn set_input_device(&mut self, port: u8, device: &str) -> bool {
+        let port = match port {
+            1 => nes_core::Port::One,
+            2 => nes_core::Port::Two,
+            _ => nes_core::Port::Expansion,
+        };
+        match device.parse() {
+            Ok(kind) => {
+                self.nes.set_input_device(port, kind);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Aims the Zapper in port 1 or 2 at a screen pixel and sets its trigger.
+    pub fn set_zapper_state(&mut self, port: u8, x: i32, y: i32, trigger: bool) {
+        let port = if port == 1 {
+            nes_core::Port::One
+        } else {
+            nes_core::Port::Two
+        };
+        self.nes.set_zapper_state(port, x, y, trigger);
+    }
+
+    /// Turns the Arkanoid paddle in port 1, 2, or 0 for the expansion port, from 0.0 (fully
+    /// left) to 1.0 (fully right), and sets its fire button.
+    pub fn set_arkanoid_state(&mut self, port: u8, position: f32, fire: bool) {
+        let port = match port {
+            1 => nes_core::Port::One,
+            2 => nes_core::Port::Two,
+            _ => nes_core::Port::Expansion,
+        };
+        self.nes.set_arkanoid_state(port, position, fire);
+    }
+
+    /// Picks a built-in palette or generated one (see `nes_core::Palette`'s `FromStr`).
+    /// Returns false if the name isn't valid.
+    pub fn set_palette(&mut self, name: &str) -> bool {
+        match name.parse() {
+            Ok(palette) => {
+                self.nes.set_palette(palette);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Loads a .pal file. Returns false if it isn't one.
+    pub fn load_palette(&mut self, data: &[u8]) -> bool {
+        match nes_core::Palette::from_pal(data) {
+            Ok(palette) => {
+                self.nes.set_palette(palette);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Adds a Game Genie code. Returns false if it isn't a valid code.
+    pub fn add_cheat(&mut self, code: &str) -> bool {
+        self.nes.add_cheat(code).is_ok()
+    }
+
+    pub fn remove_cheat(&mut self, code: &str) {
+        self.nes.remove_cheat(code);
+    }
+
+    pub fn set_cheat_enabled(&mut self, code: &str, enabled: bool) -> bool {
+        self.nes.set_cheat_enabled(code, enabled)
+    }
+
     pub fn set_controller1_state(
         &mut self,
         a: bool,
@@ -54,4 +172,27 @@ impl Emulator {
         state.down = down;
         self.nes.set_controller1_state(state);
     }
+
+    pub fn set_controller2_state(
+        &mut self,
+        a: bool,
+        b: bool,
+        select: bool,
+        start: bool,
+        left: bool,
+        right: bool,
+        up: bool,
+        down: bool,
+    ) {
+        let mut state = nes_core::ControllerState::default();
+        state.a = a;
+        state.b = b;
+        state.select = select;
+        state.start = start;
+        state.left = left;
+        state.right = right;
+        state.up = up;
+        state.down = down;
+        self.nes.set_controller2_state(state);
+    }
 }
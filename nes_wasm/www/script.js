@@ -19,6 +19,14 @@ var key_up = false;
 var key_down = false;
 var key_left = false;
 var key_right = false;
+var key2_a = false;
+var key2_b = false;
+var key2_select = false;
+var key2_start = false;
+var key2_up = false;
+var key2_down = false;
+var key2_left = false;
+var key2_right = false;
 
 function openRom(event) {
     var input = event.target;
@@ -28,6 +36,7 @@ function openRom(event) {
         var arrayBuffer = reader.result;
         var data = new Uint8Array(arrayBuffer);
         emulator = new nes.Emulator(data);
+        audio_buffer = audio_ctx.createBuffer(1, emulator.audio_samples_per_frame(), NES_SAMPLE_RATE);
 
         if (paused) {
             paused = false;
@@ -48,6 +57,16 @@ function emulatorStep() {
         key_up,
         key_down,
     );
+    emulator.set_controller2_state(
+        key2_a,
+        key2_b,
+        key2_select,
+        key2_start,
+        key2_left,
+        key2_right,
+        key2_up,
+        key2_down,
+    );
     emulator.emulate_frame();
     emulator.get_frame_buffer(canvas_data.data);
     canvas_ctx.putImageData(canvas_data, 0, 0);
@@ -89,6 +108,30 @@ function handleKey(code, down) {
         case "ArrowRight":
             key_right = down;
             break;
+        case "KeyG":
+            key2_a = down;
+            break;
+        case "KeyF":
+            key2_b = down;
+            break;
+        case "KeyQ":
+            key2_select = down;
+            break;
+        case "KeyE":
+            key2_start = down;
+            break;
+        case "KeyW":
+            key2_up = down;
+            break;
+        case "KeyS":
+            key2_down = down;
+            break;
+        case "KeyA":
+            key2_left = down;
+            break;
+        case "KeyD":
+            key2_right = down;
+            break;
     }
 }
 
@@ -106,7 +149,6 @@ async function onLoad() {
     // Initialize audio.
     var AudioContext = window.AudioContext || window.webkitAudioContext;
     audio_ctx = new AudioContext({sampleRate: NES_SAMPLE_RATE});
-    audio_buffer = audio_ctx.createBuffer(1, NES_SAMPLE_RATE / 60, NES_SAMPLE_RATE);
 
     // Initialize controls;
     let file_selector = document.getElementById("rom_input");